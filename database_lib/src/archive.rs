@@ -0,0 +1,193 @@
+//! Paged, filtered listing of the archive. Pages are cut by email id: a page
+//! ends with a cursor, and the next page starts after it, so emails archived
+//! while a client pages through never shift or repeat what it sees.
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params_from_iter, types::Value, Connection, Error as SqliteError};
+use serde::{Deserialize, Serialize};
+
+use crate::{query_emails_with_ids, Email, EMAIL_COLUMNS};
+
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// In the order they were archived.
+    #[default]
+    Oldest,
+    Newest,
+}
+
+/// Which emails to list. Unset filters match everything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailQuery {
+    /// The cursor of the previous page.
+    pub after: Option<i64>,
+    pub limit: usize,
+    /// Archived at or after.
+    pub since: Option<DateTime<Utc>>,
+    /// Archived before.
+    pub until: Option<DateTime<Utc>>,
+    /// Exact recipient address, case-insensitive.
+    pub recipient: Option<String>,
+    /// A username listed as a sender, case-insensitive.
+    pub sender: Option<String>,
+    /// Case-insensitive substring of the header.
+    pub header_contains: Option<String>,
+    pub order: SortOrder,
+}
+
+impl Default for EmailQuery {
+    fn default() -> Self {
+        Self {
+            after: None,
+            limit: 50,
+            since: None,
+            until: None,
+            recipient: None,
+            sender: None,
+            header_contains: None,
+            order: SortOrder::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailPage {
+    pub emails: Vec<Email>,
+    /// Pass as [`EmailQuery::after`] for the next page; `None` on the last page.
+    pub next: Option<i64>,
+}
+
+/// One page of the emails matching `query`.
+pub fn list_emails(conn: &Connection, query: &EmailQuery) -> Result<EmailPage, SqliteError> {
+    let mut conditions: Vec<&str> = Vec::new();
+    let mut values: Vec<Value> = Vec::new();
+    if let Some(after) = query.after {
+        conditions.push(match query.order {
+            SortOrder::Oldest => "id > ?",
+            SortOrder::Newest => "id < ?",
+        });
+        values.push(Value::Integer(after));
+    }
+    if let Some(since) = query.since {
+        conditions.push("sent_at >= ?");
+        values.push(Value::Integer(since.timestamp()));
+    }
+    if let Some(until) = query.until {
+        conditions.push("sent_at < ?");
+        values.push(Value::Integer(until.timestamp()));
+    }
+    if let Some(recipient) = &query.recipient {
+        conditions.push("recipient = ? COLLATE NOCASE");
+        values.push(Value::Text(recipient.clone()));
+    }
+    if let Some(sender) = &query.sender {
+        conditions.push("id IN (SELECT email_id FROM email_senders WHERE username = ?)");
+        values.push(Value::Text(sender.clone()));
+    }
+    if let Some(header) = &query.header_contains {
+        conditions.push("instr(lower(header), lower(?)) > 0");
+        values.push(Value::Text(header.clone()));
+    }
+
+    let mut sql = format!("SELECT {} FROM emails", EMAIL_COLUMNS);
+    if !conditions.is_empty() {
+        sql += &format!(" WHERE {}", conditions.join(" AND "));
+    }
+    sql += match query.order {
+        SortOrder::Oldest => " ORDER BY id",
+        SortOrder::Newest => " ORDER BY id DESC",
+    };
+    // one more than asked for tells whether there is a next page
+    sql += " LIMIT ?";
+    values.push(Value::Integer(query.limit as i64 + 1));
+
+    let rows = query_emails_with_ids(conn, &sql, params_from_iter(values))?;
+    Ok(query.page(rows))
+}
+
+impl EmailQuery {
+    /// Whether email `id` passes every filter; the same rules `list_emails`
+    /// applies in SQL, for stores that do not have SQL.
+    pub(crate) fn matches(&self, id: i64, email: &Email) -> bool {
+        let after = match (self.after, self.order) {
+            (None, _) => true,
+            (Some(after), SortOrder::Oldest) => id > after,
+            (Some(after), SortOrder::Newest) => id < after,
+        };
+        after
+            && self.since.is_none_or(|since| email.date >= since)
+            && self.until.is_none_or(|until| email.date < until)
+            && self.recipient.as_ref().is_none_or(|recipient| {
+                email.to.as_ref().is_some_and(|to| to.eq_ignore_ascii_case(recipient))
+            })
+            && self.sender.as_ref().is_none_or(|sender| {
+                email.senders.iter().any(|listed| listed.eq_ignore_ascii_case(sender))
+            })
+            && self.header_contains.as_ref().is_none_or(|header| {
+                email.header.to_lowercase().contains(&header.to_lowercase())
+            })
+    }
+
+    /// Cuts `rows`, in page order and at most one longer than the limit, into a page.
+    pub(crate) fn page(&self, mut rows: Vec<(i64, Email)>) -> EmailPage {
+        let next = match rows.len() > self.limit {
+            true => {
+                rows.truncate(self.limit);
+                rows.last().map(|(id, _)| *id)
+            }
+            false => None,
+        };
+        EmailPage { emails: rows.into_iter().map(|(_, email)| email).collect(), next }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{insert_email_to_database, migrate};
+
+    fn email(header: &str, senders: &[&str], date: &str) -> Email {
+        Email {
+            to: Some("team@example.org".to_string()),
+            header: header.to_string(),
+            message: "Thanks!".to_string(),
+            senders: senders.iter().map(|sender| sender.to_string()).collect(),
+            group_signature: "{}".to_string(),
+            date: DateTime::parse_from_rfc3339(date).unwrap().with_timezone(&Utc),
+            members: Vec::new(),
+            status: crate::EmailStatus::Sent,
+            timestamp: None,
+        }
+    }
+
+    fn headers(page: &EmailPage) -> Vec<&str> {
+        page.emails.iter().map(|email| email.header.as_str()).collect()
+    }
+
+    #[test]
+    fn pages_and_filters() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        insert_email_to_database(&conn, &email("Hackathon kudos", &["alice", "bob"], "2025-06-01T10:00:00Z")).unwrap();
+        insert_email_to_database(&conn, &email("Demo day", &["bob"], "2025-06-02T10:00:00Z")).unwrap();
+        insert_email_to_database(&conn, &email("More hackathon", &["alice"], "2025-06-03T10:00:00Z")).unwrap();
+
+        let mut query = EmailQuery { limit: 2, order: SortOrder::Newest, ..EmailQuery::default() };
+        let first = list_emails(&conn, &query).unwrap();
+        assert_eq!(headers(&first), ["More hackathon", "Demo day"]);
+        query.after = first.next;
+        let second = list_emails(&conn, &query).unwrap();
+        assert_eq!((headers(&second), second.next), (vec!["Hackathon kudos"], None));
+
+        let query = EmailQuery {
+            sender: Some("Alice".to_string()),
+            header_contains: Some("HACKATHON".to_string()),
+            since: Some(DateTime::parse_from_rfc3339("2025-06-02T00:00:00Z").unwrap().with_timezone(&Utc)),
+            ..EmailQuery::default()
+        };
+        assert_eq!(headers(&list_emails(&conn, &query).unwrap()), ["More hackathon"]);
+        let query = EmailQuery { recipient: Some("other@example.org".to_string()), ..EmailQuery::default() };
+        assert!(list_emails(&conn, &query).unwrap().emails.is_empty());
+    }
+}
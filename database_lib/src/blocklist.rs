@@ -0,0 +1,154 @@
+//! Admin-managed blocklist of usernames and key fingerprints, with an audit
+//! trail of every change: what was added or removed, when and why.
+
+use rusqlite::{params, Connection, Error as SqliteError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockKind {
+    /// A GitHub username.
+    User,
+    /// An OpenSSH SHA256 key fingerprint.
+    Key,
+}
+
+impl BlockKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BlockKind::User => "user",
+            BlockKind::Key => "key",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, SqliteError> {
+        match value {
+            "user" => Ok(BlockKind::User),
+            "key" => Ok(BlockKind::Key),
+            other => Err(SqliteError::InvalidColumnType(0, other.to_string(), rusqlite::types::Type::Text)),
+        }
+    }
+
+    /// Usernames are stored lowercase; fingerprints are case-sensitive.
+    fn normalize(&self, value: &str) -> String {
+        match self {
+            BlockKind::User => value.to_lowercase(),
+            BlockKind::Key => value.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+pub struct BlockEntry {
+    pub kind: BlockKind,
+    pub value: String,
+    pub reason: String,
+    pub added_at: i64,
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+pub struct BlockAuditEntry {
+    pub kind: BlockKind,
+    pub value: String,
+    /// `add` or `remove`.
+    pub action: String,
+    pub reason: String,
+    pub at: i64,
+}
+
+fn audit(conn: &Connection, kind: BlockKind, value: &str, action: &str, reason: &str, at: i64) -> Result<(), SqliteError> {
+    conn.execute(
+        "INSERT INTO blocklist_audit (kind, value, action, reason, at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![kind.as_str(), value, action, reason, at],
+    )?;
+    Ok(())
+}
+
+/// Blocks `value`. Returns `false` (and changes nothing) if it already was.
+pub fn add_block(conn: &Connection, kind: BlockKind, value: &str, reason: &str, at: i64) -> Result<bool, SqliteError> {
+    let value = kind.normalize(value);
+    let tx = conn.unchecked_transaction()?;
+    let added = tx.execute(
+        "INSERT OR IGNORE INTO blocklist (kind, value, reason, added_at) VALUES (?1, ?2, ?3, ?4)",
+        params![kind.as_str(), value, reason, at],
+    )? > 0;
+    if added {
+        audit(&tx, kind, &value, "add", reason, at)?;
+    }
+    tx.commit()?;
+    Ok(added)
+}
+
+/// Unblocks `value`. Returns `false` if it was not blocked.
+pub fn remove_block(conn: &Connection, kind: BlockKind, value: &str, reason: &str, at: i64) -> Result<bool, SqliteError> {
+    let value = kind.normalize(value);
+    let tx = conn.unchecked_transaction()?;
+    let removed = tx.execute("DELETE FROM blocklist WHERE kind = ?1 AND value = ?2", params![kind.as_str(), value])? > 0;
+    if removed {
+        audit(&tx, kind, &value, "remove", reason, at)?;
+    }
+    tx.commit()?;
+    Ok(removed)
+}
+
+/// The current entries, oldest first.
+pub fn list_blocks(conn: &Connection) -> Result<Vec<BlockEntry>, SqliteError> {
+    let mut stmt = conn.prepare_cached("SELECT kind, value, reason, added_at FROM blocklist ORDER BY added_at, kind, value")?;
+    let entries = stmt.query_map([], |row| {
+        Ok(BlockEntry {
+            kind: BlockKind::parse(&row.get::<_, String>(0)?)?,
+            value: row.get(1)?,
+            reason: row.get(2)?,
+            added_at: row.get(3)?,
+        })
+    })?;
+    entries.collect()
+}
+
+/// Every change ever made, oldest first.
+pub fn block_audit_trail(conn: &Connection) -> Result<Vec<BlockAuditEntry>, SqliteError> {
+    let mut stmt = conn.prepare_cached("SELECT kind, value, action, reason, at FROM blocklist_audit ORDER BY id")?;
+    let entries = stmt.query_map([], |row| {
+        Ok(BlockAuditEntry {
+            kind: BlockKind::parse(&row.get::<_, String>(0)?)?,
+            value: row.get(1)?,
+            action: row.get(2)?,
+            reason: row.get(3)?,
+            at: row.get(4)?,
+        })
+    })?;
+    entries.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_audited() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::migrate(&conn).unwrap();
+        assert!(add_block(&conn, BlockKind::User, "Mallory", "spam", 10).unwrap());
+        assert!(!add_block(&conn, BlockKind::User, "mallory", "again", 11).unwrap());
+        assert!(add_block(&conn, BlockKind::Key, "SHA256:abc", "leaked", 12).unwrap());
+        assert!(remove_block(&conn, BlockKind::User, "MALLORY", "appeal granted", 13).unwrap());
+        assert!(!remove_block(&conn, BlockKind::User, "mallory", "twice", 14).unwrap());
+
+        let entries = list_blocks(&conn).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].kind, entries[0].value.as_str()), (BlockKind::Key, "SHA256:abc"));
+        let trail: Vec<(String, String, i64)> = block_audit_trail(&conn)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.value, entry.action, entry.at))
+            .collect();
+        assert_eq!(
+            trail,
+            vec![
+                ("mallory".to_string(), "add".to_string(), 10),
+                ("SHA256:abc".to_string(), "add".to_string(), 12),
+                ("mallory".to_string(), "remove".to_string(), 13),
+            ]
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Consent registry: which GitHub users agreed to be named as possible senders,
+//! and the one-time challenges they sign to opt in or out.
+//!
+//! Usernames compare case-insensitively, as they do on GitHub.
+
+use rusqlite::{params, params_from_iter, Connection, Error as SqliteError, OptionalExtension};
+
+/// Stores a challenge `username` has to sign for `action`. A user may have
+/// several open challenges, so asking for one never cancels another; expired
+/// challenges are dropped here.
+pub fn store_challenge(
+    conn: &Connection,
+    username: &str,
+    nonce: &str,
+    action: &str,
+    challenge: &str,
+    now: i64,
+    expires_at: i64,
+) -> Result<(), SqliteError> {
+    conn.execute("DELETE FROM consent_challenges WHERE expires_at < ?1", params![now])?;
+    conn.execute(
+        "INSERT INTO consent_challenges (username, nonce, action, challenge, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![username, nonce, action, challenge, expires_at],
+    )?;
+    Ok(())
+}
+
+/// The challenge `username` was given for `action` under `nonce`, unless it
+/// expired before `now` or was already answered. It stays open until
+/// [`take_challenge`] consumes it.
+pub fn open_challenge(conn: &Connection, username: &str, nonce: &str, action: &str, now: i64) -> Result<Option<String>, SqliteError> {
+    conn.query_row(
+        "SELECT challenge FROM consent_challenges WHERE username = ?1 AND nonce = ?2 AND action = ?3 AND expires_at >= ?4",
+        params![username, nonce, action, now],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Consumes a challenge once its answer has been verified; returns whether it
+/// was still open, so of two concurrent answers only one counts.
+pub fn take_challenge(conn: &Connection, username: &str, nonce: &str) -> Result<bool, SqliteError> {
+    Ok(conn.execute("DELETE FROM consent_challenges WHERE username = ?1 AND nonce = ?2", params![username, nonce])? > 0)
+}
+
+pub fn record_opt_in(conn: &Connection, username: &str, key_fingerprint: &str, at: i64) -> Result<(), SqliteError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO consents (username, key_fingerprint, opted_in_at) VALUES (?1, ?2, ?3)",
+        params![username, key_fingerprint, at],
+    )?;
+    Ok(())
+}
+
+/// Returns whether `username` had opted in.
+pub fn record_opt_out(conn: &Connection, username: &str) -> Result<bool, SqliteError> {
+    Ok(conn.execute("DELETE FROM consents WHERE username = ?1", params![username])? > 0)
+}
+
+/// The members of `usernames` that opted in, in the given order.
+pub fn consenting_members(conn: &Connection, usernames: &[String]) -> Result<Vec<String>, SqliteError> {
+    if usernames.is_empty() {
+        return Ok(Vec::new());
+    }
+    let sql = format!(
+        "SELECT username FROM consents WHERE username IN ({})",
+        vec!["?"; usernames.len()].join(", ")
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let consenting: Vec<String> = stmt
+        .query_map(params_from_iter(usernames), |row| row.get::<_, String>(0))?
+        .map(|name| name.map(|name| name.to_lowercase()))
+        .collect::<Result<_, _>>()?;
+    Ok(usernames.iter().filter(|name| consenting.contains(&name.to_lowercase())).cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenges_stay_open_until_taken_and_expire() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::migrate(&conn).unwrap();
+        store_challenge(&conn, "alice", "n1", "opt-in", "first", 0, 100).unwrap();
+        store_challenge(&conn, "Alice", "n2", "opt-in", "second", 0, 100).unwrap();
+        // a second challenge does not replace the first
+        assert_eq!(open_challenge(&conn, "alice", "n1", "opt-in", 50).unwrap(), Some("first".to_string()));
+        assert_eq!(open_challenge(&conn, "alice", "n1", "opt-out", 50).unwrap(), None);
+        assert_eq!(open_challenge(&conn, "bob", "n1", "opt-in", 50).unwrap(), None);
+        assert!(take_challenge(&conn, "alice", "n1").unwrap());
+        assert!(!take_challenge(&conn, "alice", "n1").unwrap());
+        assert_eq!(open_challenge(&conn, "alice", "n1", "opt-in", 50).unwrap(), None);
+        assert_eq!(open_challenge(&conn, "alice", "n2", "opt-in", 50).unwrap(), Some("second".to_string()));
+
+        assert_eq!(open_challenge(&conn, "alice", "n2", "opt-in", 101).unwrap(), None);
+        // the expired challenge is cleared by the next one stored
+        store_challenge(&conn, "bob", "n3", "opt-in", "late", 101, 200).unwrap();
+        assert!(!take_challenge(&conn, "alice", "n2").unwrap());
+    }
+
+    #[test]
+    fn tracks_opt_ins_and_outs() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::migrate(&conn).unwrap();
+        record_opt_in(&conn, "Alice", "SHA256:a", 1).unwrap();
+        record_opt_in(&conn, "carol", "SHA256:c", 1).unwrap();
+        let members = vec!["carol".to_string(), "bob".to_string(), "alice".to_string()];
+        assert_eq!(consenting_members(&conn, &members).unwrap(), vec!["carol", "alice"]);
+
+        assert!(record_opt_out(&conn, "carol").unwrap());
+        assert!(!record_opt_out(&conn, "carol").unwrap());
+        assert_eq!(consenting_members(&conn, &members).unwrap(), vec!["alice"]);
+    }
+}
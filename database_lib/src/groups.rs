@@ -0,0 +1,156 @@
+//! Named groups managed by the server: a member list, a default recipient, a
+//! cap on their keys and the public keys pinned when the group was last saved, so
+//! submissions to the group are checked against a key set the members know.
+
+use rusqlite::{params, Connection, Error as SqliteError, OptionalExtension};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+pub struct Group {
+    pub name: String,
+    pub members: Vec<String>,
+    /// Used when a submission does not name a recipient.
+    pub recipient: Option<String>,
+    /// Largest number of distinct keys the group may have. Only a cap: every
+    /// group is still proven with the server's `MAX_GROUP_SIZE` circuit.
+    pub max_keys: usize,
+    /// Fewest members with keys a submission needs, if stricter than the
+    /// server-wide minimum.
+    #[serde(default)]
+    pub min_members: Option<usize>,
+    /// Fewest distinct keys a submission needs, if stricter than the
+    /// server-wide minimum.
+    #[serde(default)]
+    pub min_keys: Option<usize>,
+}
+
+/// A member key pinned for a group.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+pub struct PinnedKey {
+    pub username: String,
+    /// Key family name, e.g. `rsa` or `ed25519`.
+    pub key_type: String,
+    pub public_key: Vec<u8>,
+}
+
+/// Creates or replaces `group` together with its pinned keys.
+pub fn save_group(conn: &Connection, group: &Group, keys: &[PinnedKey]) -> Result<(), SqliteError> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO groups (name, recipient, max_keys, min_members, min_keys) VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (name) DO UPDATE SET recipient = excluded.recipient, max_keys = excluded.max_keys,
+            min_members = excluded.min_members, min_keys = excluded.min_keys",
+        params![
+            group.name,
+            group.recipient,
+            group.max_keys as i64,
+            group.min_members.map(|min| min as i64),
+            group.min_keys.map(|min| min as i64)
+        ],
+    )?;
+    tx.execute("DELETE FROM group_members WHERE group_name = ?1", params![group.name])?;
+    tx.execute("DELETE FROM group_keys WHERE group_name = ?1", params![group.name])?;
+    {
+        let mut insert_member =
+            tx.prepare_cached("INSERT OR IGNORE INTO group_members (group_name, position, username) VALUES (?1, ?2, ?3)")?;
+        for (position, username) in group.members.iter().enumerate() {
+            insert_member.execute(params![group.name, position as i64, username])?;
+        }
+        let mut insert_key = tx.prepare_cached(
+            "INSERT OR IGNORE INTO group_keys (group_name, username, key_type, public_key) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for key in keys {
+            insert_key.execute(params![group.name, key.username, key.key_type, key.public_key])?;
+        }
+    }
+    tx.commit()
+}
+
+pub fn get_group(conn: &Connection, name: &str) -> Result<Option<Group>, SqliteError> {
+    let group = conn
+        .query_row(
+            "SELECT name, recipient, max_keys, min_members, min_keys FROM groups WHERE name = ?1",
+            params![name],
+            |row| {
+                Ok(Group {
+                    name: row.get(0)?,
+                    recipient: row.get(1)?,
+                    max_keys: row.get::<_, i64>(2)? as usize,
+                    min_members: row.get::<_, Option<i64>>(3)?.map(|min| min as usize),
+                    min_keys: row.get::<_, Option<i64>>(4)?.map(|min| min as usize),
+                    members: Vec::new(),
+                })
+            },
+        )
+        .optional()?;
+    match group {
+        Some(mut group) => {
+            group.members = group_members(conn, &group.name)?;
+            Ok(Some(group))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Every group, by name.
+pub fn list_groups(conn: &Connection) -> Result<Vec<Group>, SqliteError> {
+    let names: Vec<String> = {
+        let mut stmt = conn.prepare_cached("SELECT name FROM groups ORDER BY name")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    let mut groups: Vec<Group> = Vec::new();
+    for name in names {
+        groups.extend(get_group(conn, &name)?);
+    }
+    Ok(groups)
+}
+
+pub fn pinned_keys(conn: &Connection, name: &str) -> Result<Vec<PinnedKey>, SqliteError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT username, key_type, public_key FROM group_keys WHERE group_name = ?1 ORDER BY username, key_type, public_key",
+    )?;
+    let keys = stmt.query_map(params![name], |row| {
+        Ok(PinnedKey { username: row.get(0)?, key_type: row.get(1)?, public_key: row.get(2)? })
+    })?;
+    keys.collect()
+}
+
+fn group_members(conn: &Connection, name: &str) -> Result<Vec<String>, SqliteError> {
+    let mut stmt = conn.prepare_cached("SELECT username FROM group_members WHERE group_name = ?1 ORDER BY position")?;
+    let members = stmt.query_map(params![name], |row| row.get(0))?;
+    members.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(username: &str, byte: u8) -> PinnedKey {
+        PinnedKey { username: username.to_string(), key_type: "rsa".to_string(), public_key: vec![byte; 4] }
+    }
+
+    #[test]
+    fn saves_and_replaces_groups() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::migrate(&conn).unwrap();
+        let mut group = Group {
+            name: "kudos-team".to_string(),
+            members: vec!["carol".to_string(), "alice".to_string()],
+            recipient: Some("team@example.org".to_string()),
+            max_keys: 300,
+            min_members: None,
+            min_keys: Some(3),
+        };
+        save_group(&conn, &group, &[key("alice", 1), key("carol", 2)]).unwrap();
+        assert_eq!(get_group(&conn, "kudos-team").unwrap(), Some(group.clone()));
+        assert_eq!(get_group(&conn, "other").unwrap(), None);
+
+        group.members = vec!["bob".to_string()];
+        group.recipient = None;
+        save_group(&conn, &group, &[key("bob", 3)]).unwrap();
+        assert_eq!(list_groups(&conn).unwrap(), vec![group]);
+        assert_eq!(pinned_keys(&conn, "kudos-team").unwrap(), vec![key("bob", 3)]);
+    }
+}
@@ -0,0 +1,146 @@
+//! History of every public key seen per username, so that a proof made just
+//! before a member rotated their key can still be checked against the key set
+//! the prover saw.
+//!
+//! Each key keeps the first and last time (UNIX seconds) a fetch returned it.
+//! A key is considered live at time `t` when `first_seen <= t <= last_seen`.
+
+use rusqlite::{params, params_from_iter, Connection, Error as SqliteError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Eq)]
+pub struct KeyRecord {
+    pub username: String,
+    /// Key family name, e.g. `rsa` or `ed25519`.
+    pub key_type: String,
+    pub public_key: Vec<u8>,
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+/// Records that a fetch at `seen_at` returned `keys` (`(key_type, public_key)`
+/// pairs) for `username`: new keys are added, known keys have `last_seen`
+/// moved forward.
+pub fn record_seen_keys(
+    conn: &Connection,
+    username: &str,
+    keys: &[(String, Vec<u8>)],
+    seen_at: i64,
+) -> Result<(), SqliteError> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO key_history (username, key_type, public_key, first_seen, last_seen) VALUES (?1, ?2, ?3, ?4, ?4)
+         ON CONFLICT (username, key_type, public_key) DO UPDATE SET
+            first_seen = MIN(first_seen, excluded.first_seen),
+            last_seen = MAX(last_seen, excluded.last_seen)",
+    )?;
+    for (key_type, public_key) in keys {
+        stmt.execute(params![username, key_type, public_key, seen_at])?;
+    }
+    Ok(())
+}
+
+/// Every key ever seen for `username`, oldest first.
+pub fn key_history(conn: &Connection, username: &str) -> Result<Vec<KeyRecord>, SqliteError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT username, key_type, public_key, first_seen, last_seen FROM key_history
+         WHERE username = ?1 ORDER BY first_seen, key_type, public_key",
+    )?;
+    let records = stmt.query_map(params![username], row_to_record)?;
+    records.collect()
+}
+
+/// Points in time within `[since, before)` at which one of `usernames` last
+/// saw a key that is gone afterwards, newest first. These are the moments
+/// just before a rotation, i.e. the key sets worth retrying.
+pub fn rotation_times(
+    conn: &Connection,
+    usernames: &[String],
+    since: i64,
+    before: i64,
+) -> Result<Vec<i64>, SqliteError> {
+    if usernames.is_empty() {
+        return Ok(Vec::new());
+    }
+    let sql = format!(
+        "SELECT DISTINCT last_seen FROM key_history
+         WHERE username IN ({}) AND last_seen >= ? AND last_seen < ? ORDER BY last_seen DESC",
+        placeholders(usernames.len())
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mut values: Vec<rusqlite::types::Value> = usernames.iter().map(|name| name.clone().into()).collect();
+    values.push(since.into());
+    values.push(before.into());
+    let times = stmt.query_map(params_from_iter(values), |row| row.get(0))?;
+    times.collect()
+}
+
+/// The keys of `usernames` that were live at `at`.
+pub fn keys_live_at(conn: &Connection, usernames: &[String], at: i64) -> Result<Vec<KeyRecord>, SqliteError> {
+    if usernames.is_empty() {
+        return Ok(Vec::new());
+    }
+    let sql = format!(
+        "SELECT username, key_type, public_key, first_seen, last_seen FROM key_history
+         WHERE username IN ({}) AND first_seen <= ? AND last_seen >= ? ORDER BY username, key_type, public_key",
+        placeholders(usernames.len())
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mut values: Vec<rusqlite::types::Value> = usernames.iter().map(|name| name.clone().into()).collect();
+    values.push(at.into());
+    values.push(at.into());
+    let records = stmt.query_map(params_from_iter(values), row_to_record)?;
+    records.collect()
+}
+
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+fn row_to_record(row: &rusqlite::Row) -> Result<KeyRecord, SqliteError> {
+    Ok(KeyRecord {
+        username: row.get(0)?,
+        key_type: row.get(1)?,
+        public_key: row.get(2)?,
+        first_seen: row.get(3)?,
+        last_seen: row.get(4)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn tracks_rotations() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::migrate(&conn).unwrap();
+        let old = vec![("rsa".to_string(), vec![1u8])];
+        let new = vec![("rsa".to_string(), vec![2u8])];
+        record_seen_keys(&conn, "alice", &old, 100).unwrap();
+        record_seen_keys(&conn, "alice", &old, 200).unwrap();
+        record_seen_keys(&conn, "alice", &new, 300).unwrap();
+        record_seen_keys(&conn, "bob", &[("ed25519".to_string(), vec![3u8; 32])], 300).unwrap();
+
+        let history = key_history(&conn, "alice").unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!((history[0].first_seen, history[0].last_seen), (100, 200));
+        assert_eq!((history[1].first_seen, history[1].last_seen), (300, 300));
+
+        let group = names(&["alice", "bob"]);
+        assert_eq!(rotation_times(&conn, &group, 0, 300).unwrap(), vec![200]);
+        assert!(rotation_times(&conn, &group, 250, 300).unwrap().is_empty());
+        let live: Vec<Vec<u8>> = keys_live_at(&conn, &group, 200).unwrap().into_iter().map(|key| key.public_key).collect();
+        assert_eq!(live, vec![vec![1u8]]);
+        assert_eq!(keys_live_at(&conn, &group, 300).unwrap().len(), 2);
+        // GitHub usernames are case-insensitive
+        record_seen_keys(&conn, "Alice", &new, 400).unwrap();
+        let history = key_history(&conn, "ALICE").unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].last_seen, 400);
+        assert_eq!(keys_live_at(&conn, &names(&["ALICE"]), 200).unwrap().len(), 1);
+    }
+}
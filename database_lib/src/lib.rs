@@ -1,6 +1,28 @@
-use rusqlite::{params, Connection, Result};
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, params_from_iter, Connection, Result};
 use serde::{Serialize, Deserialize};
 
+pub mod archive;
+pub use archive::{list_emails, EmailPage, EmailQuery, SortOrder};
+pub mod blocklist;
+pub use blocklist::{add_block, block_audit_trail, list_blocks, remove_block, BlockAuditEntry, BlockEntry, BlockKind};
+pub mod consent;
+pub use consent::{consenting_members, open_challenge, record_opt_in, record_opt_out, store_challenge, take_challenge};
+pub mod groups;
+pub use groups::{get_group, list_groups, pinned_keys, save_group, Group, PinnedKey};
+pub mod key_history;
+pub use key_history::{key_history, keys_live_at, record_seen_keys, rotation_times, KeyRecord};
+pub mod migrations;
+pub use migrations::{migrate, schema_version, Migration, MigrationError, SCHEMA_VERSION};
+pub mod pool;
+pub use pool::{ConnectionPool, PooledConnection};
+pub mod search;
+pub use search::{search_emails, SearchHit};
+pub mod store;
+pub use store::{EmailStore, InMemoryEmailStore, SqliteEmailStore, StoreError};
+
 #[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
 pub struct Email{
     pub to: Option<String>, 
@@ -8,75 +30,173 @@ pub struct Email{
     pub message: String,
     pub senders: Vec<String>,
     pub group_signature: String,
-    pub date: String
+    /// When the email was archived; serialized as RFC 3339.
+    pub date: DateTime<Utc>,
+    /// Every member of the group the senders were drawn from, after `org:` and
+    /// `team:` references were resolved.
+    #[serde(default)]
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub status: EmailStatus,
+    /// UNIX timestamp the senders signed along with the message, if they
+    /// signed one; the proof cannot be checked again without it.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
 }
 
+/// Whether the letter for an archived email went out.
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailStatus {
+    /// Archived, not sent yet.
+    #[default]
+    Pending,
+    Sent,
+    /// The mail server refused the letter.
+    Failed,
+}
 
-use rusqlite::Error as SqliteError;
-
+impl EmailStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EmailStatus::Pending => "pending",
+            EmailStatus::Sent => "sent",
+            EmailStatus::Failed => "failed",
+        }
+    }
 
-pub fn create_table(conn: &Connection) -> Result<usize, SqliteError> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS emails (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            recipient TEXT,
-            header TEXT,
-            message TEXT,
-            senders TEXT NOT NULL,
-            group_signature TEXT NOT NULL,
-            date TEXT NOT NULL
-        )",
-        [],
-    )
+    fn parse(value: &str) -> Result<Self, SqliteError> {
+        match value {
+            "pending" => Ok(EmailStatus::Pending),
+            "sent" => Ok(EmailStatus::Sent),
+            "failed" => Ok(EmailStatus::Failed),
+            other => Err(SqliteError::InvalidColumnType(6, other.to_string(), rusqlite::types::Type::Text)),
+        }
+    }
 }
 
-// assuming senders is a vector of strings
-// add proper error handling
+
+use rusqlite::Error as SqliteError;
+
+
+// the legacy `senders` and `members` columns are left empty; the lists live
+// in `email_senders` and `email_members`.
+// `date` keeps a readable copy of `sent_at`, which is what is queried
 pub fn insert_email_to_database(conn: &Connection, email: &Email) -> Result<i64, SqliteError> {
-    conn.execute(
-        "INSERT INTO emails (recipient, header, message, senders, group_signature, date) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        params![email.to, email.header, email.message, email.senders.join(","), email.group_signature, email.date],
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO emails (recipient, header, message, senders, group_signature, date, sent_at, members, status, signed_at)
+         VALUES (?1, ?2, ?3, '', ?4, ?5, ?6, '', ?7, ?8)",
+        params![
+            email.to,
+            email.header,
+            email.message,
+            email.group_signature,
+            email.date.to_rfc3339(),
+            email.date.timestamp(),
+            email.status.as_str(),
+            email.timestamp
+        ],
     )?;
-    Ok(conn.last_insert_rowid())
+    let id = tx.last_insert_rowid();
+    insert_list(&tx, "email_senders", id, &email.senders)?;
+    insert_list(&tx, "email_members", id, &email.members)?;
+    tx.commit()?;
+    Ok(id)
 }
 
-pub fn get_email_from_database(conn: &Connection, id: i64) -> Result<Email, SqliteError> {
-    let mut stmt = conn.prepare("SELECT id, recipient, header, message, senders, group_signature, date FROM emails WHERE id = ?1")?;
-    let email_iter = stmt.query_map(params![id], |row| {
-        Ok(Email {
-            to: row.get(1)?,
-            header: row.get(2)?,
-            message: row.get(3)?,
-            senders: row.get::<_, String>(4)?.split(',').map(String::from).collect(),
-            group_signature: row.get(5)?,
-            date: row.get(6)?,
-        })
-    })?;
-
-    for email in email_iter {
-        return email;
+fn insert_list(conn: &Connection, table: &str, email_id: i64, usernames: &[String]) -> Result<(), SqliteError> {
+    let mut stmt = conn.prepare_cached(&format!("INSERT INTO {table} (email_id, position, username) VALUES (?1, ?2, ?3)"))?;
+    for (position, username) in usernames.iter().enumerate() {
+        stmt.execute(params![email_id, position as i64, username])?;
     }
-    Err(SqliteError::QueryReturnedNoRows)
+    Ok(())
 }
 
-pub fn list_all_emails_in_database(conn : &Connection) -> Result<Vec<Email>, SqliteError>{
-    let mut stmt = conn.prepare("SELECT * FROM emails")?;
-    let email_iter = stmt.query_map([], |row| {
-        Ok(Email {
+pub(crate) const EMAIL_COLUMNS: &str = "id, recipient, header, message, group_signature, sent_at, status, signed_at";
+
+// every column but the senders and members, which are read separately
+fn email_from_row(row: &rusqlite::Row) -> Result<(i64, Email), SqliteError> {
+    Ok((
+        row.get(0)?,
+        Email {
             to: row.get(1)?,
             header: row.get(2)?,
             message: row.get(3)?,
-            senders: row.get::<_, String>(4)?.split(',').map(String::from).collect(),
-            group_signature: row.get(5)?,
-            date: row.get(6)?,
-        })
-    });
-    let mut all_emails : Vec<Email> = Vec::new();
-    for email in email_iter? {
-        all_emails.push(email?);
+            senders: Vec::new(),
+            group_signature: row.get(4)?,
+            date: timestamp(row.get(5)?)?,
+            members: Vec::new(),
+            status: EmailStatus::parse(&row.get::<_, String>(6)?)?,
+            timestamp: row.get(7)?,
+        },
+    ))
+}
+
+fn timestamp(seconds: i64) -> Result<DateTime<Utc>, SqliteError> {
+    DateTime::from_timestamp(seconds, 0).ok_or(SqliteError::IntegralValueOutOfRange(5, seconds))
+}
+
+// ids per `IN (...)` list, well under SQLite's limit on bound parameters
+pub(crate) const IDS_PER_QUERY: usize = 500;
+
+// runs an `EMAIL_COLUMNS` query and fills in the senders and members of
+// every email it returned, a chunk of emails per query rather than one
+// query per email
+pub(crate) fn query_emails_with_ids(conn: &Connection, sql: &str, params: impl rusqlite::Params) -> Result<Vec<(i64, Email)>, SqliteError> {
+    // the SQL is built per call, so it is not worth a statement cache slot
+    let mut stmt = conn.prepare(sql)?;
+    let mut rows: Vec<(i64, Email)> = stmt.query_map(params, email_from_row)?.collect::<Result<_, _>>()?;
+    let index: HashMap<i64, usize> = rows.iter().enumerate().map(|(row, (id, _))| (*id, row)).collect();
+    for chunk in rows.iter().map(|(id, _)| *id).collect::<Vec<i64>>().chunks(IDS_PER_QUERY) {
+        let ids = vec!["?"; chunk.len()].join(", ");
+        let sql = format!(
+            "SELECT email_id, 0, username, position FROM email_senders WHERE email_id IN ({ids})
+             UNION ALL SELECT email_id, 1, username, position FROM email_members WHERE email_id IN ({ids})
+             ORDER BY 1, 2, 4"
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let mut lists = stmt.query(params_from_iter(chunk.iter().chain(chunk)))?;
+        while let Some(row) = lists.next()? {
+            let email = &mut rows[index[&row.get::<_, i64>(0)?]].1;
+            match row.get::<_, i64>(1)? {
+                0 => email.senders.push(row.get(2)?),
+                _ => email.members.push(row.get(2)?),
+            }
+        }
+    }
+    Ok(rows)
+}
+
+fn query_emails(conn: &Connection, sql: &str, params: impl rusqlite::Params) -> Result<Vec<Email>, SqliteError> {
+    Ok(query_emails_with_ids(conn, sql, params)?.into_iter().map(|(_, email)| email).collect())
+}
+
+/// Returns `false` if there is no email `id`.
+pub fn set_email_status(conn: &Connection, id: i64, status: EmailStatus) -> Result<bool, SqliteError> {
+    Ok(conn.execute("UPDATE emails SET status = ?2 WHERE id = ?1", params![id, status.as_str()])? > 0)
+}
+
+pub fn get_email_from_database(conn: &Connection, id: i64) -> Result<Email, SqliteError> {
+    let sql = format!("SELECT {EMAIL_COLUMNS} FROM emails WHERE id = ?1");
+    match query_emails(conn, &sql, params![id])?.pop() {
+        Some(email) => Ok(email),
+        None => Err(SqliteError::QueryReturnedNoRows),
     }
-    Ok(all_emails)
-}   
+}
+
+pub fn list_all_emails_in_database(conn : &Connection) -> Result<Vec<Email>, SqliteError>{
+    query_emails(conn, &format!("SELECT {EMAIL_COLUMNS} FROM emails ORDER BY id"), [])
+}
+
+/// Every email `username` was listed as a sender of, oldest first. Usernames
+/// compare case-insensitively, as on GitHub.
+pub fn list_emails_by_sender(conn: &Connection, username: &str) -> Result<Vec<Email>, SqliteError> {
+    let sql = format!(
+        "SELECT {EMAIL_COLUMNS} FROM emails WHERE id IN (SELECT email_id FROM email_senders WHERE username = ?1) ORDER BY id"
+    );
+    query_emails(conn, &sql, params![username])
+}
 
 #[cfg(test)]
 mod tests {
@@ -84,30 +204,34 @@ mod tests {
 
     #[test]
     fn it_works() {
-        let conn = Connection::open("emails.db").expect("Failed to open database");
-        create_table(&conn).expect("Failed to create table");
+        let conn = Connection::open_in_memory().expect("Failed to open database");
+        migrate(&conn).expect("Failed to migrate database");
         let email = Email {
             to: Some("duruozer13@gmail.com".to_string()),
             header: "Test Email".to_string(),
             message: "This is a test email.".to_string(),
             senders: vec!["sender1".to_string(), "sender2".to_string()],
             group_signature: "I know a password".to_string(),
-            date: "2025-06-18".to_string(),
+            date: DateTime::from_timestamp(1_750_240_800, 0).unwrap(),
+            members: vec!["sender1".to_string(), "sender2".to_string(), "sender3".to_string()],
+            status: EmailStatus::Pending,
+            timestamp: Some(1_750_240_700),
         };
         let email_id = insert_email_to_database(&conn, &email).expect("Failed to insert email");
         //email.id = email_id; // Update the email struct with the new ID
         // TODO: then send the email
-        println!("Email inserted successfully with ID: {}", email_id);
-        match get_email_from_database(&conn, email_id as i64) {
-            Ok(test_email) => assert!(email == test_email),
-            Err(e) => println!("Error retrieving email: {}", e),
+        assert_eq!(get_email_from_database(&conn, email_id).expect("Failed to read the email back"), email);
+        assert!(set_email_status(&conn, email_id, EmailStatus::Sent).unwrap());
+        assert_eq!(get_email_from_database(&conn, email_id).unwrap().status, EmailStatus::Sent);
+        assert!(!set_email_status(&conn, email_id + 1, EmailStatus::Sent).unwrap());
+        assert_eq!(list_emails_by_sender(&conn, "SENDER2").unwrap().len(), 1);
+        match list_emails_by_sender(&conn, "sender1,sender2") {
+            Ok(emails) => assert!(emails.is_empty()),
+            Err(e) => panic!("Error listing emails by sender: {}", e),
         };
         match list_all_emails_in_database(&conn) {
-            Ok(all_emails) => {
-                assert!(all_emails.len() > 0);
-                println!("All emails in database: {:?}", all_emails);
-            },
-            Err(e) => assert!(false, "Error listing emails: {}", e),
+            Ok(all_emails) => assert_eq!(all_emails, vec![Email { status: EmailStatus::Sent, ..email }]),
+            Err(e) => panic!("Error listing emails: {}", e),
         };
     }
 }
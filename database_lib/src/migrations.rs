@@ -0,0 +1,348 @@
+//! Versioned schema migrations. The database records how many migrations it
+//! has applied in `PRAGMA user_version`; [`migrate`] applies the rest, in
+//! order, in one transaction, and refuses databases written by a newer binary.
+//!
+//! Migrations are append-only: once released, a migration is never edited,
+//! a new one is added instead.
+//!
+//! Databases created before the runner existed are at version 0 with any
+//! subset of the early tables, so migrations 1 to 7 only create what is
+//! missing.
+
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use rusqlite::{params, types::Type, Connection, Error as SqliteError};
+
+/// One schema change.
+pub enum Migration {
+    /// Statements run with `execute_batch`.
+    Sql(&'static str),
+    /// Changes that need to read the database first, e.g. to rewrite rows.
+    Rust(fn(&Connection) -> Result<(), SqliteError>),
+}
+
+/// Every migration, oldest first; migration `n` brings the database to version `n`.
+pub const MIGRATIONS: &[Migration] = &[
+    // 1: the archive
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS emails (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            recipient TEXT,
+            header TEXT,
+            message TEXT,
+            senders TEXT NOT NULL,
+            group_signature TEXT NOT NULL,
+            date TEXT NOT NULL
+        );",
+    ),
+    // 2: the resolved group of each email
+    Migration::Rust(|conn| add_column_if_missing(conn, "emails", "members", "TEXT NOT NULL DEFAULT ''")),
+    // 3: keys seen per user
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS key_history (
+            username TEXT NOT NULL,
+            key_type TEXT NOT NULL,
+            public_key BLOB NOT NULL,
+            first_seen INTEGER NOT NULL,
+            last_seen INTEGER NOT NULL,
+            PRIMARY KEY (username, key_type, public_key)
+        );",
+    ),
+    // 4: named groups and their pinned keys
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS groups (
+            name TEXT PRIMARY KEY,
+            recipient TEXT,
+            circuit_size INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS group_members (
+            group_name TEXT NOT NULL REFERENCES groups (name),
+            position INTEGER NOT NULL,
+            username TEXT NOT NULL,
+            PRIMARY KEY (group_name, username)
+        );
+        CREATE TABLE IF NOT EXISTS group_keys (
+            group_name TEXT NOT NULL REFERENCES groups (name),
+            username TEXT NOT NULL,
+            key_type TEXT NOT NULL,
+            public_key BLOB NOT NULL,
+            PRIMARY KEY (group_name, username, key_type, public_key)
+        );",
+    ),
+    // 5: the consent registry
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS consent_challenges (
+            username TEXT PRIMARY KEY COLLATE NOCASE,
+            action TEXT NOT NULL,
+            challenge TEXT NOT NULL,
+            expires_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS consents (
+            username TEXT PRIMARY KEY COLLATE NOCASE,
+            key_fingerprint TEXT NOT NULL,
+            opted_in_at INTEGER NOT NULL
+        );",
+    ),
+    // 6: the blocklist and its audit trail
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS blocklist (
+            kind TEXT NOT NULL,
+            value TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            added_at INTEGER NOT NULL,
+            PRIMARY KEY (kind, value)
+        );
+        CREATE TABLE IF NOT EXISTS blocklist_audit (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            value TEXT NOT NULL,
+            action TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            at INTEGER NOT NULL
+        );",
+    ),
+    // 7: per-group anonymity minimums
+    Migration::Rust(|conn| {
+        add_column_if_missing(conn, "groups", "min_members", "INTEGER")?;
+        add_column_if_missing(conn, "groups", "min_keys", "INTEGER")
+    }),
+    // 8: senders in their own table, so names may contain commas and can be looked up
+    Migration::Sql(
+        "CREATE TABLE email_senders (
+            email_id INTEGER NOT NULL REFERENCES emails (id),
+            position INTEGER NOT NULL,
+            username TEXT NOT NULL COLLATE NOCASE,
+            PRIMARY KEY (email_id, position)
+        );
+        CREATE INDEX email_senders_by_username ON email_senders (username, email_id);",
+    ),
+    Migration::Rust(|conn| move_list(conn, "senders", "email_senders")),
+    // 10: full-text index over header and message, kept in sync by triggers
+    Migration::Sql(
+        "CREATE VIRTUAL TABLE emails_fts USING fts5(header, message, content = 'emails', content_rowid = 'id');
+        CREATE TRIGGER emails_fts_insert AFTER INSERT ON emails BEGIN
+            INSERT INTO emails_fts (rowid, header, message) VALUES (new.id, new.header, new.message);
+        END;
+        CREATE TRIGGER emails_fts_delete AFTER DELETE ON emails BEGIN
+            INSERT INTO emails_fts (emails_fts, rowid, header, message) VALUES ('delete', old.id, old.header, old.message);
+        END;
+        CREATE TRIGGER emails_fts_update AFTER UPDATE OF header, message ON emails BEGIN
+            INSERT INTO emails_fts (emails_fts, rowid, header, message) VALUES ('delete', old.id, old.header, old.message);
+            INSERT INTO emails_fts (rowid, header, message) VALUES (new.id, new.header, new.message);
+        END;
+        INSERT INTO emails_fts (emails_fts) VALUES ('rebuild');",
+    ),
+    // 11: dates as UTC UNIX seconds
+    Migration::Rust(add_sent_at),
+    // 12: delivery status; earlier emails were archived as they were sent
+    Migration::Sql("ALTER TABLE emails ADD COLUMN status TEXT NOT NULL DEFAULT 'sent';"),
+    // 13: several open consent challenges per user, told apart by their nonce;
+    // challenges are short-lived, so open ones are dropped rather than moved
+    Migration::Sql(
+        "DROP TABLE consent_challenges;
+        CREATE TABLE consent_challenges (
+            username TEXT NOT NULL COLLATE NOCASE,
+            nonce TEXT NOT NULL,
+            action TEXT NOT NULL,
+            challenge TEXT NOT NULL,
+            expires_at INTEGER NOT NULL,
+            PRIMARY KEY (username, nonce)
+        );",
+    ),
+    // 14: resolved members in their own table as well
+    Migration::Sql(
+        "CREATE TABLE email_members (
+            email_id INTEGER NOT NULL REFERENCES emails (id),
+            position INTEGER NOT NULL,
+            username TEXT NOT NULL COLLATE NOCASE,
+            PRIMARY KEY (email_id, position)
+        );",
+    ),
+    Migration::Rust(|conn| move_list(conn, "members", "email_members")),
+    // 16: the timestamp the senders signed, if they signed one
+    Migration::Sql("ALTER TABLE emails ADD COLUMN signed_at INTEGER;"),
+    // 17: the group size never chose a circuit, it only caps the keys
+    Migration::Sql("ALTER TABLE groups RENAME COLUMN circuit_size TO max_keys;"),
+    // 18: key history usernames compare case-insensitively, as on GitHub;
+    // keys recorded under two spellings of one name are merged
+    Migration::Sql(
+        "CREATE TABLE key_history_nocase (
+            username TEXT NOT NULL COLLATE NOCASE,
+            key_type TEXT NOT NULL,
+            public_key BLOB NOT NULL,
+            first_seen INTEGER NOT NULL,
+            last_seen INTEGER NOT NULL,
+            PRIMARY KEY (username, key_type, public_key)
+        );
+        INSERT INTO key_history_nocase (username, key_type, public_key, first_seen, last_seen)
+            SELECT MIN(username), key_type, public_key, MIN(first_seen), MAX(last_seen) FROM key_history
+            GROUP BY username COLLATE NOCASE, key_type, public_key;
+        DROP TABLE key_history;
+        ALTER TABLE key_history_nocase RENAME TO key_history;",
+    ),
+];
+
+/// The schema version this binary writes.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlite(SqliteError),
+    /// The database was migrated by a newer binary; running against it could
+    /// lose data the older code does not know about.
+    TooNew { found: u32, supported: u32 },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::Sqlite(err) => write!(f, "migration failed: {}", err),
+            MigrationError::TooNew { found, supported } => write!(
+                f,
+                "database schema version {} is newer than this binary supports ({})",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<SqliteError> for MigrationError {
+    fn from(err: SqliteError) -> Self {
+        MigrationError::Sqlite(err)
+    }
+}
+
+pub fn schema_version(conn: &Connection) -> Result<u32, SqliteError> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Brings the database up to [`SCHEMA_VERSION`] and returns the version it
+/// was at. Either every pending migration is applied or none is.
+pub fn migrate(conn: &Connection) -> Result<u32, MigrationError> {
+    let tx = conn.unchecked_transaction()?;
+    let found = schema_version(&tx)?;
+    if found > SCHEMA_VERSION {
+        return Err(MigrationError::TooNew { found, supported: SCHEMA_VERSION });
+    }
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(found as usize) {
+        match migration {
+            Migration::Sql(sql) => tx.execute_batch(sql)?,
+            Migration::Rust(apply) => apply(&tx)?,
+        }
+        tx.pragma_update(None, "user_version", version as u32 + 1)?;
+    }
+    tx.commit()?;
+    Ok(found)
+}
+
+// 9 and 15: copies a comma-joined column of `emails` into its own table and
+// empties it. The emptied column stays, as dropping it would mean rebuilding
+// `emails` under its full-text triggers; inserts write '' to it.
+fn move_list(conn: &Connection, column: &str, table: &str) -> Result<(), SqliteError> {
+    let rows: Vec<(i64, String)> = {
+        let mut stmt = conn.prepare(&format!("SELECT id, {column} FROM emails WHERE {column} <> ''"))?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    let mut insert = conn.prepare(&format!("INSERT INTO {table} (email_id, position, username) VALUES (?1, ?2, ?3)"))?;
+    for (id, joined) in rows {
+        for (position, username) in joined.split(',').filter(|username| !username.is_empty()).enumerate() {
+            insert.execute(params![id, position as i64, username])?;
+        }
+    }
+    conn.execute(&format!("UPDATE emails SET {column} = ''"), [])?;
+    Ok(())
+}
+
+// 11: `date` was written as `%Y-%m-%d %H:%M:%S` in UTC, without a zone;
+// it becomes `sent_at`, and `date` keeps an RFC 3339 copy
+fn add_sent_at(conn: &Connection) -> Result<(), SqliteError> {
+    conn.execute_batch(
+        "ALTER TABLE emails ADD COLUMN sent_at INTEGER NOT NULL DEFAULT 0;
+        CREATE INDEX emails_by_sent_at ON emails (sent_at, id);",
+    )?;
+    let rows: Vec<(i64, String)> = {
+        let mut stmt = conn.prepare("SELECT id, date FROM emails")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    let mut update = conn.prepare("UPDATE emails SET sent_at = ?2, date = ?3 WHERE id = ?1")?;
+    for (id, date) in rows {
+        let parsed = NaiveDateTime::parse_from_str(&date, "%Y-%m-%d %H:%M:%S")
+            .or_else(|_| NaiveDate::parse_from_str(&date, "%Y-%m-%d").map(|day| day.and_time(NaiveTime::MIN)))
+            .map(|naive| naive.and_utc())
+            .or_else(|_| DateTime::parse_from_rfc3339(&date).map(|date| date.with_timezone(&Utc)))
+            .map_err(|err| SqliteError::FromSqlConversionFailure(0, Type::Text, Box::new(err)))?;
+        update.execute(params![id, parsed.timestamp(), parsed.to_rfc3339()])?;
+    }
+    Ok(())
+}
+
+/// Adds a column to a table created before it existed.
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<(), SqliteError> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+        params![table, column],
+        |row| row.get::<_, i64>(0),
+    )? > 0;
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrades_databases_created_before_the_runner() {
+        let conn = Connection::open_in_memory().unwrap();
+        // the schema `create_table` used to make
+        conn.execute_batch(
+            "CREATE TABLE emails (
+                id INTEGER PRIMARY KEY AUTOINCREMENT, recipient TEXT, header TEXT, message TEXT,
+                senders TEXT NOT NULL, group_signature TEXT NOT NULL, date TEXT NOT NULL,
+                members TEXT NOT NULL DEFAULT ''
+            );
+            INSERT INTO emails (recipient, header, message, senders, group_signature, date, members)
+                VALUES ('team@example.org', 'Kudos', 'Thanks', 'alice,bob', '{}', '2025-06-18 10:00:00', 'alice,bob,carol');
+            CREATE TABLE key_history (
+                username TEXT NOT NULL, key_type TEXT NOT NULL, public_key BLOB NOT NULL,
+                first_seen INTEGER NOT NULL, last_seen INTEGER NOT NULL,
+                PRIMARY KEY (username, key_type, public_key)
+            );
+            INSERT INTO key_history VALUES ('alice', 'rsa', x'01', 100, 200), ('Alice', 'rsa', x'01', 50, 150);",
+        )
+        .unwrap();
+
+        assert_eq!(migrate(&conn).unwrap(), 0);
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        let (senders, members): (String, String) =
+            conn.query_row("SELECT senders, members FROM emails", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert_eq!((senders.as_str(), members.as_str()), ("", ""));
+        let email = crate::get_email_from_database(&conn, 1).unwrap();
+        assert_eq!(email.senders, vec!["alice", "bob"]);
+        assert_eq!(email.members, vec!["alice", "bob", "carol"]);
+        assert_eq!(email.date.to_rfc3339(), "2025-06-18T10:00:00+00:00");
+        // both spellings of alice are one history entry now
+        let history = crate::key_history(&conn, "ALICE").unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!((history[0].first_seen, history[0].last_seen), (50, 200));
+        // nothing left to do
+        assert_eq!(migrate(&conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn refuses_newer_databases() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+        assert!(matches!(
+            migrate(&conn),
+            Err(MigrationError::TooNew { found, supported }) if found == SCHEMA_VERSION + 1 && supported == SCHEMA_VERSION
+        ));
+    }
+}
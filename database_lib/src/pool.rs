@@ -0,0 +1,152 @@
+//! A small pool of SQLite connections. File databases run in WAL mode, so
+//! readers do not wait for the writer; writers wait for each other up to
+//! [`BUSY_TIMEOUT`] instead of failing with `SQLITE_BUSY`.
+//!
+//! Checking out a connection blocks until one is free, so callers in async
+//! code should do so on a blocking thread.
+
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use rusqlite::{Connection, Error as SqliteError};
+
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Prepared statements kept per connection, see `Connection::prepare_cached`.
+pub const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+#[derive(Clone)]
+pub struct ConnectionPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    // `None` for the single connection of an in-memory pool
+    path: Option<PathBuf>,
+    size: usize,
+    state: Mutex<PoolState>,
+    returned: Condvar,
+}
+
+struct PoolState {
+    idle: Vec<Connection>,
+    // idle plus checked out
+    open: usize,
+}
+
+/// A checked-out connection; goes back to the pool when dropped.
+pub struct PooledConnection {
+    conn: Option<Connection>,
+    pool: Arc<PoolInner>,
+}
+
+fn configure(conn: &Connection) -> Result<(), SqliteError> {
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    Ok(())
+}
+
+impl ConnectionPool {
+    /// Opens up to `size` connections to the database at `path`, one now and
+    /// the rest as they are needed.
+    pub fn open(path: impl Into<PathBuf>, size: usize) -> Result<Self, SqliteError> {
+        let path = path.into();
+        let conn = Connection::open(&path)?;
+        // persistent: recorded in the database file
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        configure(&conn)?;
+        Ok(Self::with_first(Some(path), size.max(1), conn))
+    }
+
+    /// A private in-memory database. Each connection to `:memory:` is a
+    /// database of its own, so the pool holds exactly one.
+    pub fn in_memory() -> Result<Self, SqliteError> {
+        let conn = Connection::open_in_memory()?;
+        configure(&conn)?;
+        Ok(Self::with_first(None, 1, conn))
+    }
+
+    fn with_first(path: Option<PathBuf>, size: usize, conn: Connection) -> Self {
+        let state = PoolState { idle: vec![conn], open: 1 };
+        Self { inner: Arc::new(PoolInner { path, size, state: Mutex::new(state), returned: Condvar::new() }) }
+    }
+
+    /// A free connection, waiting for one if all are in use.
+    pub fn get(&self) -> Result<PooledConnection, SqliteError> {
+        let mut state = self.inner.state.lock().unwrap();
+        loop {
+            if let Some(conn) = state.idle.pop() {
+                return Ok(PooledConnection { conn: Some(conn), pool: self.inner.clone() });
+            }
+            if let Some(path) = &self.inner.path
+                && state.open < self.inner.size
+            {
+                state.open += 1;
+                // opened without holding the lock; the slot is given back on failure
+                drop(state);
+                let opened = Connection::open(path).and_then(|conn| configure(&conn).map(|_| conn));
+                return match opened {
+                    Ok(conn) => Ok(PooledConnection { conn: Some(conn), pool: self.inner.clone() }),
+                    Err(err) => {
+                        self.inner.state.lock().unwrap().open -= 1;
+                        self.inner.returned.notify_one();
+                        Err(err)
+                    }
+                };
+            }
+            state = self.inner.returned.wait(state).unwrap();
+        }
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.state.lock().unwrap().idle.push(conn);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readers_and_writers_share_a_wal_database() {
+        let path = std::env::temp_dir().join(format!("database_lib-pool-{}.db", std::process::id()));
+        let pool = ConnectionPool::open(&path, 2).unwrap();
+        crate::migrate(&pool.get().unwrap()).unwrap();
+
+        let writer = pool.get().unwrap();
+        let reader = pool.get().unwrap();
+        let journal_mode: String = reader.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode, "wal");
+        // a reader is not blocked by an open write transaction
+        writer.execute_batch("BEGIN IMMEDIATE; INSERT INTO consents VALUES ('alice', 'SHA256:abc', 1);").unwrap();
+        let count: i64 = reader.query_row("SELECT COUNT(*) FROM consents", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 0);
+        writer.execute_batch("COMMIT").unwrap();
+
+        // both connections are out: the next checkout waits for one to come back
+        let waiting = {
+            let pool = pool.clone();
+            std::thread::spawn(move || pool.get().unwrap().query_row("SELECT COUNT(*) FROM consents", [], |row| row.get::<_, i64>(0)).unwrap())
+        };
+        drop(reader);
+        assert_eq!(waiting.join().unwrap(), 1);
+        drop((writer, pool));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Full-text search over the header and message of archived emails, backed by
+//! the `emails_fts` FTS5 index.
+//!
+//! The search text is taken as words, not FTS5 query syntax: every word must
+//! appear, and the last one may be the start of a word, so "hack" finds
+//! "hackathon".
+//!
+//! The highlighted header and snippet are HTML: the archived text is escaped
+//! and matches are wrapped in [`HIGHLIGHT_START`] and [`HIGHLIGHT_END`], so
+//! clients can render them as markup.
+
+use std::collections::HashMap;
+
+use rusqlite::{params, params_from_iter, Connection, Error as SqliteError};
+use serde::{Deserialize, Serialize};
+
+use crate::{query_emails_with_ids, Email, EMAIL_COLUMNS, IDS_PER_QUERY};
+
+/// Wraps matched words in snippets.
+pub const HIGHLIGHT_START: &str = "<mark>";
+pub const HIGHLIGHT_END: &str = "</mark>";
+
+// what FTS5 puts around matches; replaced by the highlight tags once the text
+// around them is escaped. A sender could write them too, but at most that
+// adds stray highlight tags, never markup of their own.
+const MATCH_START: &str = "\u{2}";
+const MATCH_END: &str = "\u{3}";
+
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct SearchHit {
+    pub id: i64,
+    pub email: Email,
+    /// BM25 score; lower is more relevant.
+    pub rank: f64,
+    /// The header as HTML, with matches highlighted.
+    pub header: String,
+    /// The part of the message around the best match, as HTML, highlighted.
+    pub snippet: String,
+}
+
+/// Escapes the characters HTML gives a meaning to.
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn mark_up(highlighted: &str) -> String {
+    escape_html(highlighted).replace(MATCH_START, HIGHLIGHT_START).replace(MATCH_END, HIGHLIGHT_END)
+}
+
+// each word becomes a quoted FTS5 string, so quotes and operators in the
+// search text are matched literally
+fn fts_query(text: &str) -> Option<String> {
+    let words: Vec<String> = text.split_whitespace().map(|word| format!("\"{}\"", word.replace('"', "\"\""))).collect();
+    let last = words.last()?;
+    let mut query = words[..words.len() - 1].to_vec();
+    query.push(format!("{}*", last));
+    Some(query.join(" "))
+}
+
+/// The `limit` emails that best match `text`, most relevant first.
+pub fn search_emails(conn: &Connection, text: &str, limit: usize) -> Result<Vec<SearchHit>, SqliteError> {
+    let Some(query) = fts_query(text) else {
+        return Ok(Vec::new());
+    };
+    let rows: Vec<(i64, f64, String, String)> = {
+        let mut stmt = conn.prepare_cached(
+            "SELECT rowid, bm25(emails_fts),
+                highlight(emails_fts, 0, ?2, ?3),
+                snippet(emails_fts, 1, ?2, ?3, '…', 16)
+             FROM emails_fts WHERE emails_fts MATCH ?1 ORDER BY bm25(emails_fts) LIMIT ?4",
+        )?;
+        let rows = stmt.query_map(params![query, MATCH_START, MATCH_END, limit as i64], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
+        rows.collect::<Result<_, _>>()?
+    };
+    // the matching emails are loaded a chunk at a time, not one query per hit
+    let ids: Vec<i64> = rows.iter().map(|(id, ..)| *id).collect();
+    let mut emails: HashMap<i64, Email> = HashMap::with_capacity(ids.len());
+    for chunk in ids.chunks(IDS_PER_QUERY) {
+        let sql = format!("SELECT {EMAIL_COLUMNS} FROM emails WHERE id IN ({})", vec!["?"; chunk.len()].join(", "));
+        emails.extend(query_emails_with_ids(conn, &sql, params_from_iter(chunk))?);
+    }
+    let mut hits: Vec<SearchHit> = Vec::with_capacity(rows.len());
+    for (id, rank, header, snippet) in rows {
+        let email = emails.remove(&id).ok_or(SqliteError::QueryReturnedNoRows)?;
+        hits.push(SearchHit { id, email, rank, header: mark_up(&header), snippet: mark_up(&snippet) });
+    }
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{insert_email_to_database, migrate};
+
+    fn email(header: &str, message: &str) -> Email {
+        Email {
+            to: None,
+            header: header.to_string(),
+            message: message.to_string(),
+            senders: vec!["alice".to_string()],
+            group_signature: "{}".to_string(),
+            date: chrono::DateTime::from_timestamp(1_750_240_800, 0).unwrap(),
+            members: Vec::new(),
+            status: crate::EmailStatus::Sent,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn finds_and_highlights_matches() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        insert_email_to_database(&conn, &email("Kudos", "Great work at the hackathon, the demo was fun")).unwrap();
+        let hackathon = insert_email_to_database(&conn, &email("Hackathon kudos", "Thanks for organizing the hackathon")).unwrap();
+        insert_email_to_database(&conn, &email("Demo day", "Nice slides")).unwrap();
+
+        let hits = search_emails(&conn, "hackath", 10).unwrap();
+        assert_eq!(hits.len(), 2);
+        // matches in both header and message rank first
+        assert_eq!(hits[0].id, hackathon);
+        assert_eq!(hits[0].header, "<mark>Hackathon</mark> kudos");
+        assert_eq!((hits[0].email.header.as_str(), hits[0].email.senders.as_slice()), ("Hackathon kudos", ["alice".to_string()].as_slice()));
+        assert!(hits[0].snippet.contains("<mark>hackathon</mark>"), "{}", hits[0].snippet);
+
+        assert_eq!(search_emails(&conn, "demo \"slides", 10).unwrap().len(), 1);
+        assert!(search_emails(&conn, "   ", 10).unwrap().is_empty());
+        // what senders wrote is escaped, only the highlight is markup
+        insert_email_to_database(&conn, &email("<img src=x onerror=alert(1)> & slides", "<script>alert('slides')</script>")).unwrap();
+        let hits = search_emails(&conn, "slides", 10).unwrap();
+        let hit = hits.iter().find(|hit| hit.header.contains("img")).unwrap();
+        assert_eq!(hit.header, "&lt;img src=x onerror=alert(1)&gt; &amp; <mark>slides</mark>");
+        assert_eq!(hit.snippet, "&lt;script&gt;alert(&#39;<mark>slides</mark>&#39;)&lt;/script&gt;");
+        conn.execute("UPDATE emails SET message = 'edited' WHERE id = ?1", params![hackathon]).unwrap();
+        assert_eq!(search_emails(&conn, "organizing", 10).unwrap().len(), 0);
+    }
+}
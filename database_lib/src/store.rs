@@ -0,0 +1,255 @@
+//! The archive behind a trait, so the server can run on SQLite or, in tests
+//! and throwaway setups, entirely in memory.
+
+use std::fmt;
+use std::sync::Mutex;
+
+use rusqlite::Error as SqliteError;
+
+use crate::search::{escape_html, HIGHLIGHT_END, HIGHLIGHT_START};
+use crate::{
+    get_email_from_database, insert_email_to_database, list_emails, search_emails, set_email_status, Email, EmailPage,
+    ConnectionPool, EmailQuery, EmailStatus, SearchHit, SortOrder,
+};
+
+/// Why an [`EmailStore`] call failed.
+#[derive(Debug)]
+pub enum StoreError {
+    Sqlite(SqliteError),
+    /// A failure of any other backend.
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Sqlite(err) => write!(f, "archive database failed: {}", err),
+            StoreError::Backend(err) => write!(f, "archive failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Sqlite(err) => Some(err),
+            StoreError::Backend(err) => Some(err.as_ref()),
+        }
+    }
+}
+
+impl From<SqliteError> for StoreError {
+    fn from(err: SqliteError) -> Self {
+        StoreError::Sqlite(err)
+    }
+}
+
+pub trait EmailStore: Send + Sync {
+    /// Archives `email` and returns its id.
+    fn insert(&self, email: &Email) -> Result<i64, StoreError>;
+    fn get(&self, id: i64) -> Result<Option<Email>, StoreError>;
+    fn list(&self, query: &EmailQuery) -> Result<EmailPage, StoreError>;
+    /// The `limit` emails that best match `text`, most relevant first.
+    fn search(&self, text: &str, limit: usize) -> Result<Vec<SearchHit>, StoreError>;
+    /// Returns `false` if there is no email `id`.
+    fn set_status(&self, id: i64, status: EmailStatus) -> Result<bool, StoreError>;
+}
+
+/// The archive in a migrated SQLite database, sharing the pool with the rest
+/// of the server's tables.
+pub struct SqliteEmailStore {
+    pool: ConnectionPool,
+}
+
+impl SqliteEmailStore {
+    pub fn new(pool: ConnectionPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl EmailStore for SqliteEmailStore {
+    fn insert(&self, email: &Email) -> Result<i64, StoreError> {
+        Ok(insert_email_to_database(&*self.pool.get()?, email)?)
+    }
+
+    fn get(&self, id: i64) -> Result<Option<Email>, StoreError> {
+        match get_email_from_database(&*self.pool.get()?, id) {
+            Ok(email) => Ok(Some(email)),
+            Err(SqliteError::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn list(&self, query: &EmailQuery) -> Result<EmailPage, StoreError> {
+        Ok(list_emails(&*self.pool.get()?, query)?)
+    }
+
+    fn search(&self, text: &str, limit: usize) -> Result<Vec<SearchHit>, StoreError> {
+        Ok(search_emails(&*self.pool.get()?, text, limit)?)
+    }
+
+    fn set_status(&self, id: i64, status: EmailStatus) -> Result<bool, StoreError> {
+        Ok(set_email_status(&*self.pool.get()?, id, status)?)
+    }
+}
+
+/// Keeps emails in a vector; nothing survives the process.
+#[derive(Default)]
+pub struct InMemoryEmailStore {
+    emails: Mutex<Vec<(i64, Email)>>,
+}
+
+impl InMemoryEmailStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// words as FTS5's default tokenizer sees them: runs of letters and digits
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+// escapes `text` and wraps every word matching one of `terms` (the last as a
+// prefix), like `search_emails`; returns how many matches there were
+fn highlight(text: &str, terms: &[String]) -> (String, usize) {
+    let mut result = String::new();
+    let mut copied = 0;
+    let mut matches = 0;
+    for (start, word) in words(text) {
+        let lower = word.to_lowercase();
+        let matched = terms.iter().enumerate().any(|(index, term)| match index + 1 == terms.len() {
+            true => lower.starts_with(term.as_str()),
+            false => lower == *term,
+        });
+        if matched {
+            result.push_str(&escape_html(&text[copied..start]));
+            result.push_str(HIGHLIGHT_START);
+            result.push_str(&escape_html(word));
+            result.push_str(HIGHLIGHT_END);
+            copied = start + word.len();
+            matches += 1;
+        }
+    }
+    result.push_str(&escape_html(&text[copied..]));
+    (result, matches)
+}
+
+impl EmailStore for InMemoryEmailStore {
+    fn insert(&self, email: &Email) -> Result<i64, StoreError> {
+        let mut emails = self.emails.lock().unwrap();
+        let id = emails.last().map_or(1, |(id, _)| id + 1);
+        emails.push((id, email.clone()));
+        Ok(id)
+    }
+
+    fn get(&self, id: i64) -> Result<Option<Email>, StoreError> {
+        let emails = self.emails.lock().unwrap();
+        Ok(emails.iter().find(|(known, _)| *known == id).map(|(_, email)| email.clone()))
+    }
+
+    fn list(&self, query: &EmailQuery) -> Result<EmailPage, StoreError> {
+        let emails = self.emails.lock().unwrap();
+        let matching = emails.iter().filter(|(id, email)| query.matches(*id, email)).cloned();
+        let rows: Vec<(i64, Email)> = match query.order {
+            SortOrder::Oldest => matching.take(query.limit + 1).collect(),
+            SortOrder::Newest => matching.rev().take(query.limit + 1).collect(),
+        };
+        Ok(query.page(rows))
+    }
+
+    // every word must appear; ranked by the number of matches, unlike BM25,
+    // and the whole message is returned as the snippet
+    fn search(&self, text: &str, limit: usize) -> Result<Vec<SearchHit>, StoreError> {
+        let terms: Vec<String> = words(text).map(|(_, word)| word.to_lowercase()).collect();
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let emails = self.emails.lock().unwrap();
+        let mut hits: Vec<SearchHit> = Vec::new();
+        for (id, email) in emails.iter() {
+            let found: Vec<String> =
+                words(&email.header).chain(words(&email.message)).map(|(_, word)| word.to_lowercase()).collect();
+            let all_found = terms.iter().enumerate().all(|(index, term)| match index + 1 == terms.len() {
+                true => found.iter().any(|word| word.starts_with(term.as_str())),
+                false => found.contains(term),
+            });
+            if all_found {
+                let (header, header_matches) = highlight(&email.header, &terms);
+                let (snippet, message_matches) = highlight(&email.message, &terms);
+                let rank = -((header_matches + message_matches) as f64);
+                hits.push(SearchHit { id: *id, email: email.clone(), rank, header, snippet });
+            }
+        }
+        hits.sort_by(|a, b| a.rank.total_cmp(&b.rank).then(a.id.cmp(&b.id)));
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    fn set_status(&self, id: i64, status: EmailStatus) -> Result<bool, StoreError> {
+        let mut emails = self.emails.lock().unwrap();
+        match emails.iter_mut().find(|(known, _)| *known == id) {
+            Some((_, email)) => {
+                email.status = status;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn email(header: &str, senders: &[&str]) -> Email {
+        Email {
+            to: Some("team@example.org".to_string()),
+            header: header.to_string(),
+            message: "Thanks for the hackathon demo".to_string(),
+            senders: senders.iter().map(|sender| sender.to_string()).collect(),
+            group_signature: "{}".to_string(),
+            date: DateTime::from_timestamp(1_750_240_800, 0).unwrap(),
+            members: Vec::new(),
+            status: EmailStatus::Pending,
+            timestamp: None,
+        }
+    }
+
+    // both stores must behave the same
+    fn exercise(store: &dyn EmailStore) {
+        let first = store.insert(&email("Hackathon kudos", &["alice", "bob"])).unwrap();
+        let second = store.insert(&email("Demo day", &["bob"])).unwrap();
+        assert_eq!(store.get(first).unwrap().unwrap().senders, vec!["alice", "bob"]);
+        assert_eq!(store.get(second + 1).unwrap(), None);
+
+        assert!(store.set_status(second, EmailStatus::Failed).unwrap());
+        assert!(!store.set_status(second + 1, EmailStatus::Sent).unwrap());
+        assert_eq!(store.get(second).unwrap().unwrap().status, EmailStatus::Failed);
+
+        let query = EmailQuery { limit: 1, order: SortOrder::Newest, sender: Some("BOB".to_string()), ..EmailQuery::default() };
+        let page = store.list(&query).unwrap();
+        assert_eq!((page.emails[0].header.as_str(), page.next), ("Demo day", Some(second)));
+        let page = store.list(&EmailQuery { after: page.next, ..query }).unwrap();
+        assert_eq!((page.emails[0].header.as_str(), page.next), ("Hackathon kudos", None));
+
+        let hits = store.search("hackathon de", 10).unwrap();
+        assert_eq!(hits.iter().map(|hit| hit.id).collect::<Vec<_>>(), vec![first, second]);
+        assert_eq!(hits[0].header, "<mark>Hackathon</mark> kudos");
+        assert!(store.search("hackathon nothing", 10).unwrap().is_empty());
+
+        store.insert(&email("<b>Launch</b> & more", &["alice"])).unwrap();
+        assert_eq!(store.search("launch", 10).unwrap()[0].header, "&lt;b&gt;<mark>Launch</mark>&lt;/b&gt; &amp; more");
+    }
+
+    #[test]
+    fn stores_agree() {
+        let pool = ConnectionPool::in_memory().unwrap();
+        crate::migrate(&pool.get().unwrap()).unwrap();
+        exercise(&SqliteEmailStore::new(pool));
+        exercise(&InMemoryEmailStore::new());
+    }
+}
@@ -0,0 +1,143 @@
+//! Usernames and keys that must not take part in any group, e.g. because a
+//! private key leaked. Keys are named by their OpenSSH SHA256 fingerprint
+//! (`ssh-keygen -l`), whatever source they were fetched from.
+//!
+//! Blocked keys are left out of key sets, so they never reach the public
+//! signals; blocked users are refused.
+
+use std::collections::BTreeSet;
+
+use crate::{FetchError, GroupKeySet, KeyFamilies, KeyFamiliesBuilder, KeyType, PublicKey, Result};
+
+/// OpenSSH SHA256 fingerprint of circuit key material, as `ssh-keygen -l`
+/// prints it for the published key (see [`PublicKey::from_key_material`]).
+pub fn key_fingerprint(key_type: KeyType, key: &[u8]) -> String {
+    PublicKey::from_key_material(key_type, key).fingerprint()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Blocklist {
+    usernames: BTreeSet<String>,
+    fingerprints: BTreeSet<String>,
+}
+
+/// A key left out of a group because it is blocked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockedKey {
+    pub key_type: KeyType,
+    pub fingerprint: String,
+    /// Members that publish the key.
+    pub usernames: Vec<String>,
+}
+
+impl Blocklist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Usernames compare case-insensitively, as on GitHub.
+    pub fn block_user(&mut self, username: &str) -> &mut Self {
+        self.usernames.insert(username.to_lowercase());
+        self
+    }
+
+    /// `fingerprint` as printed by `ssh-keygen -l`, e.g. `SHA256:LDJI...`.
+    pub fn block_key(&mut self, fingerprint: &str) -> &mut Self {
+        self.fingerprints.insert(fingerprint.to_string());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.usernames.is_empty() && self.fingerprints.is_empty()
+    }
+
+    pub fn is_user_blocked(&self, username: &str) -> bool {
+        self.usernames.contains(&username.to_lowercase())
+    }
+
+    pub fn is_key_blocked(&self, key_type: KeyType, key: &[u8]) -> bool {
+        !self.fingerprints.is_empty() && self.fingerprints.contains(&key_fingerprint(key_type, key))
+    }
+
+    /// Refuses `senders` if any of them is blocked.
+    pub fn check_senders(&self, senders: &[String]) -> Result<()> {
+        let blocked: Vec<String> = senders.iter().filter(|sender| self.is_user_blocked(sender)).cloned().collect();
+        match blocked.is_empty() {
+            true => Ok(()),
+            false => Err(FetchError::BlockedSenders { senders: blocked }),
+        }
+    }
+
+    /// `families` without the blocked keys, and the keys that were left out.
+    /// Members whose only keys were blocked end up without keys; keys skipped
+    /// while fetching stay reported.
+    pub fn filter_families(&self, families: KeyFamilies) -> Result<(KeyFamilies, Vec<BlockedKey>)> {
+        if self.fingerprints.is_empty() {
+            return Ok((families, Vec::new()));
+        }
+        let mut builder = KeyFamiliesBuilder::new();
+        for member in families.members() {
+            builder.add_member(member);
+        }
+        for err in families.skipped() {
+            builder.skip_key(err.clone());
+        }
+        let mut blocked: Vec<BlockedKey> = Vec::new();
+        for key_type in families.key_types() {
+            for key in families.get(key_type).map(GroupKeySet::keys).unwrap_or_default() {
+                if self.is_key_blocked(key_type, &key.public_key) {
+                    blocked.push(BlockedKey {
+                        key_type,
+                        fingerprint: key_fingerprint(key_type, &key.public_key),
+                        usernames: key.usernames.clone(),
+                    });
+                    continue;
+                }
+                for username in &key.usernames {
+                    builder.add_key(username, key_type, &key.public_key);
+                }
+            }
+        }
+        Ok((builder.build()?, blocked))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocked_keys_are_left_out() {
+        let mut builder = KeyFamiliesBuilder::new();
+        builder
+            .add_key("alice", KeyType::Rsa, &[0xa1; 128])
+            .add_key("bob", KeyType::Rsa, &[0xb0; 128])
+            .add_key("bob", KeyType::Ed25519, &[0xb1; 32])
+            .skip_key(FetchError::malformed("certificates are not accepted").for_user("bob"));
+        let families = builder.build().unwrap();
+        let skipped = families.skipped().to_vec();
+        let mut blocklist = Blocklist::new();
+        blocklist.block_key(&key_fingerprint(KeyType::Rsa, &[0xb0; 128]));
+
+        let (filtered, blocked) = blocklist.filter_families(families).unwrap();
+        assert_eq!(blocked.len(), 1);
+        assert_eq!(blocked[0].usernames, vec!["bob"]);
+        let rsa = filtered.get(KeyType::Rsa).unwrap();
+        assert_eq!(rsa.len(), 1);
+        assert_eq!(rsa.members_without_keys(), ["bob"]);
+        assert_eq!(filtered.get(KeyType::Ed25519).unwrap().len(), 1);
+        assert_eq!(filtered.skipped(), skipped);
+    }
+
+    #[test]
+    fn blocked_users_are_refused() {
+        let mut blocklist = Blocklist::new();
+        blocklist.block_user("Mallory");
+        let senders = vec!["alice".to_string(), "mallory".to_string()];
+        assert_eq!(
+            blocklist.check_senders(&senders),
+            Err(FetchError::BlockedSenders { senders: vec!["mallory".to_string()] })
+        );
+        assert!(blocklist.check_senders(&senders[..1]).is_ok());
+    }
+}
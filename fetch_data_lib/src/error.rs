@@ -0,0 +1,206 @@
+//! The error type of every fallible function in this crate.
+//!
+//! Variants carry what a caller needs to react: the username and HTTP status
+//! of a failed download, the position of a key that could not be parsed, the
+//! members of a group that ended up without keys. [`FetchError::is_retryable`]
+//! tells transient failures apart from permanent ones.
+
+use std::fmt;
+
+use crate::KeyType;
+
+pub type Result<T> = std::result::Result<T, FetchError>;
+
+/// [`FetchError::MalformedKey`] with a `format!`ted reason.
+macro_rules! malformed {
+    ($($arg:tt)*) => {
+        $crate::FetchError::malformed(format!($($arg)*))
+    };
+}
+pub(crate) use malformed;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchError {
+    /// The key server does not know the user (HTTP 404).
+    UserNotFound { username: String },
+    /// The GitHub organization or team (`org:<org>`, `team:<org>/<team>`)
+    /// does not exist or is not visible with the configured token.
+    GroupNotFound { group: String },
+    /// The organization or team lists more members than are paged through,
+    /// so the group would be cut short.
+    MembershipTooLarge { group: String, max: usize },
+    /// The key server throttled the request (HTTP 429, or 403 with no
+    /// requests remaining). `retry_after` is in seconds, when the server
+    /// said so.
+    RateLimited { username: String, status: u16, retry_after: Option<u64> },
+    /// Any other non-success HTTP status.
+    Http { username: String, status: u16 },
+    /// The request could not be sent or the response body not be read.
+    Network { username: String, reason: String },
+    /// A published key could not be understood. `key_index` is the 1-based
+    /// position of the key (its line, for OpenSSH text) in the document.
+    MalformedKey { username: Option<String>, key_index: Option<usize>, reason: String },
+    /// No members were given.
+    NoMembers,
+    /// None of the members publishes a usable key of the family.
+    NoUsableKeys { members: Vec<String>, key_type: KeyType },
+    /// More distinct keys than the circuit was compiled for.
+    GroupTooLarge { keys: usize, max: usize },
+    /// Senders without a key, refused by `MembershipPolicy::Reject`.
+    IneligibleSenders { senders: Vec<String>, key_type: KeyType },
+    /// Senders on the blocklist.
+    BlockedSenders { senders: Vec<String> },
+    /// A signature could not be made or does not verify.
+    Signature { reason: String },
+    /// Talking to ssh-agent failed.
+    Agent { reason: String },
+    /// An argument or configuration value is not valid.
+    InvalidInput { reason: String },
+}
+
+impl FetchError {
+    pub(crate) fn malformed(reason: impl Into<String>) -> Self {
+        FetchError::MalformedKey { username: None, key_index: None, reason: reason.into() }
+    }
+
+    pub(crate) fn invalid_input(reason: impl Into<String>) -> Self {
+        FetchError::InvalidInput { reason: reason.into() }
+    }
+
+    /// Attributes a [`FetchError::MalformedKey`] to `username`, unless it
+    /// already names one. Other variants are returned unchanged.
+    pub fn for_user(self, username: &str) -> Self {
+        match self {
+            FetchError::MalformedKey { username: None, key_index, reason } => FetchError::MalformedKey {
+                username: Some(username.to_string()),
+                key_index,
+                reason,
+            },
+            other => other,
+        }
+    }
+
+    /// Records the position of the key a [`FetchError::MalformedKey`] is
+    /// about, unless it is already known.
+    pub fn at_key(self, index: usize) -> Self {
+        match self {
+            FetchError::MalformedKey { username, key_index: None, reason } => FetchError::MalformedKey {
+                username,
+                key_index: Some(index),
+                reason,
+            },
+            other => other,
+        }
+    }
+
+    /// HTTP status returned by the key server, if the failure came from one.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            FetchError::UserNotFound { .. } | FetchError::GroupNotFound { .. } => Some(404),
+            FetchError::RateLimited { status, .. } | FetchError::Http { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether the same request may succeed later: rate limits, network
+    /// failures and server-side (5xx) errors.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            FetchError::RateLimited { .. } | FetchError::Network { .. } => true,
+            FetchError::Http { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FetchError::UserNotFound { username } => write!(f, "User '{}' was not found on the key server", username),
+            FetchError::GroupNotFound { group } => {
+                write!(f, "Group '{}' was not found on GitHub or its members are not visible", group)
+            }
+            FetchError::MembershipTooLarge { group, max } => write!(
+                f,
+                "Group '{}' has more than {} members; name a smaller team or list the members instead",
+                group, max
+            ),
+            FetchError::RateLimited { username, status, retry_after } => {
+                write!(f, "Key server rate limited the request for user '{}' (HTTP {})", username, status)?;
+                match retry_after {
+                    Some(seconds) => write!(f, ", retry after {} s", seconds),
+                    None => Ok(()),
+                }
+            }
+            FetchError::Http { username, status } => write!(
+                f,
+                "Key server returned a non-success status code for user '{}': HTTP {}",
+                username, status
+            ),
+            FetchError::Network { username, reason } => {
+                write!(f, "HTTP request error for user '{}': {}", username, reason)
+            }
+            FetchError::MalformedKey { username, key_index, reason } => {
+                write!(f, "Unable to parse key")?;
+                if let Some(index) = key_index {
+                    write!(f, " #{}", index)?;
+                }
+                if let Some(username) = username {
+                    write!(f, " of user '{}'", username)?;
+                }
+                write!(f, ": {}", reason)
+            }
+            FetchError::NoMembers => write!(f, "Cannot build a group: no members were given"),
+            FetchError::NoUsableKeys { members, key_type } => write!(
+                f,
+                "Cannot build a group: none of the members ({}) has a usable {} key",
+                members.join(", "),
+                key_type
+            ),
+            FetchError::GroupTooLarge { keys, max } => write!(
+                f,
+                "Too many keys in the group: {} distinct keys, maximum allowed is {}",
+                keys, max
+            ),
+            FetchError::IneligibleSenders { senders, key_type } => write!(
+                f,
+                "The following senders have no usable {} key and cannot be part of the group: {}",
+                key_type,
+                senders.join(", ")
+            ),
+            FetchError::BlockedSenders { senders } => {
+                write!(f, "The following senders are blocked and cannot be part of a group: {}", senders.join(", "))
+            }
+            FetchError::Signature { reason } => write!(f, "Signature error: {}", reason),
+            FetchError::Agent { reason } => write!(f, "ssh-agent error: {}", reason),
+            FetchError::InvalidInput { reason } => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_is_added_once() {
+        let err = FetchError::malformed("bad base64").at_key(3).for_user("alice").at_key(7).for_user("bob");
+        assert_eq!(
+            err,
+            FetchError::MalformedKey { username: Some("alice".into()), key_index: Some(3), reason: "bad base64".into() }
+        );
+        assert_eq!(err.to_string(), "Unable to parse key #3 of user 'alice': bad base64");
+    }
+
+    #[test]
+    fn only_transient_failures_are_retryable() {
+        let username = "alice".to_string();
+        assert!(FetchError::RateLimited { username: username.clone(), status: 429, retry_after: None }.is_retryable());
+        assert!(FetchError::Http { username: username.clone(), status: 502 }.is_retryable());
+        assert!(!FetchError::Http { username: username.clone(), status: 410 }.is_retryable());
+        assert!(!FetchError::UserNotFound { username }.is_retryable());
+        assert_eq!(FetchError::NoMembers.status(), None);
+    }
+}
@@ -0,0 +1,425 @@
+//! Canonical construction of the group key set that is fed to the circuit.
+//!
+//! Keys are collected per username, deduplicated by RSA modulus (two accounts
+//! sharing one key contribute a single entry) and sorted numerically so that
+//! the same group always produces the same `keys` public input, regardless of
+//! the order in which members were listed or fetched.
+//!
+//! A key set holds keys of a single [`KeyType`]; [`KeyFamiliesBuilder`]
+//! splits a group into one key set per family.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+
+use crate::{import_rsa_public_keys, FetchError, KeyType, Result, MAX_GROUP_SIZE};
+
+/// Domain-separation tag mixed into the key-set hash.
+const KEY_SET_HASH_TAG: &[u8] = b"send_group_emails/key-set/v1";
+
+/// A single distinct key in the group together with every member that
+/// published it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupKey {
+    /// Key material as described in [`KeyType`]; for RSA the big-endian
+    /// modulus without leading zero bytes.
+    pub public_key: Vec<u8>,
+    /// The key split into the little-endian limbs of its family's circuit
+    /// (35 limbs of 120 bits for RSA).
+    pub limbs: Vec<u128>,
+    /// Usernames that published this key, sorted.
+    pub usernames: Vec<String>,
+}
+
+/// Deduplicated, canonically ordered set of the keys of one family in a group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupKeySet {
+    key_type: KeyType,
+    keys: Vec<GroupKey>,
+    members_without_keys: Vec<String>,
+    hash: [u8; 32],
+}
+
+impl GroupKeySet {
+    /// Family of every key in the set.
+    pub fn key_type(&self) -> KeyType {
+        self.key_type
+    }
+
+    /// The distinct keys, sorted by their big-endian value.
+    pub fn keys(&self) -> &[GroupKey] {
+        &self.keys
+    }
+
+    /// Number of distinct keys in the group.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Always `false`: the builder refuses to produce an empty key set.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Members that were added to the builder but did not contribute any key.
+    pub fn members_without_keys(&self) -> &[String] {
+        &self.members_without_keys
+    }
+
+    /// Maps every contributing username to the indices (into `keys()`) of the
+    /// keys it published.
+    pub fn contributions(&self) -> BTreeMap<String, Vec<usize>> {
+        let mut result: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (index, key) in self.keys.iter().enumerate() {
+            for username in &key.usernames {
+                result.entry(username.clone()).or_default().push(index);
+            }
+        }
+        result
+    }
+
+    /// SHA-256 over the canonical key list. Identical groups hash identically;
+    /// non-RSA sets also cover the family name.
+    pub fn hash(&self) -> [u8; 32] {
+        self.hash
+    }
+
+    /// Hex encoding of [`GroupKeySet::hash`].
+    pub fn hash_hex(&self) -> String {
+        self.hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Returns exactly `size` limb vectors, repeating the first key as padding.
+    /// Repeating a member key does not widen the anonymity set, so it is safe.
+    pub fn padded_limbs(&self, size: usize) -> Result<Vec<Vec<u128>>> {
+        if self.keys.len() > size {
+            return Err(FetchError::GroupTooLarge { keys: self.keys.len(), max: size });
+        }
+        let mut result: Vec<Vec<u128>> = self.keys.iter().map(|key| key.limbs.clone()).collect();
+        while result.len() < size {
+            result.push(self.keys[0].limbs.clone());
+        }
+        Ok(result)
+    }
+}
+
+/// Collects members and their keys of one family and produces a
+/// [`GroupKeySet`]. The family defaults to RSA.
+#[derive(Debug, Clone)]
+pub struct GroupKeySetBuilder {
+    key_type: KeyType,
+    max_size: usize,
+    members: BTreeSet<String>,
+    keys: BTreeMap<BigUint, BTreeSet<String>>,
+}
+
+impl Default for GroupKeySetBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GroupKeySetBuilder {
+    pub fn new() -> Self {
+        Self {
+            key_type: KeyType::Rsa,
+            max_size: MAX_GROUP_SIZE,
+            members: BTreeSet::new(),
+            keys: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the family of the keys that will be added.
+    pub fn key_type(mut self, key_type: KeyType) -> Self {
+        self.key_type = key_type;
+        self
+    }
+
+    /// Overrides the maximum number of distinct keys (defaults to
+    /// `MAX_GROUP_SIZE`, the size the circuit was compiled for).
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Registers a member, even if it ends up contributing no key.
+    pub fn add_member(&mut self, username: &str) -> &mut Self {
+        self.members.insert(username.to_string());
+        self
+    }
+
+    /// Registers `public_key` (an RSA modulus in big-endian, or the key
+    /// material of the builder's family) as a key published by `username`.
+    pub fn add_key(&mut self, username: &str, public_key: &[u8]) -> &mut Self {
+        self.add_member(username);
+        self.keys
+            .entry(BigUint::from_bytes_be(public_key))
+            .or_default()
+            .insert(username.to_string());
+        self
+    }
+
+    pub fn build(self) -> Result<GroupKeySet> {
+        if self.keys.is_empty() {
+            if self.members.is_empty() {
+                return Err(FetchError::NoMembers);
+            }
+            return Err(FetchError::NoUsableKeys {
+                members: self.members.into_iter().collect(),
+                key_type: self.key_type,
+            });
+        }
+        if self.keys.len() > self.max_size {
+            return Err(FetchError::GroupTooLarge { keys: self.keys.len(), max: self.max_size });
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(KEY_SET_HASH_TAG);
+        if self.key_type != KeyType::Rsa {
+            hasher.update(self.key_type.name());
+        }
+        hasher.update((self.keys.len() as u32).to_be_bytes());
+
+        let mut contributors: BTreeSet<String> = BTreeSet::new();
+        let mut keys: Vec<GroupKey> = Vec::new();
+        for (value, usernames) in self.keys {
+            let public_key = fixed_length(value.to_bytes_be(), self.key_type.key_len());
+            hasher.update((public_key.len() as u32).to_be_bytes());
+            hasher.update(&public_key);
+            let limbs = self
+                .key_type
+                .key_to_limbs(&public_key)
+                .map_err(|err| err.for_user(&join(&usernames)))?;
+            contributors.extend(usernames.iter().cloned());
+            keys.push(GroupKey {
+                public_key,
+                limbs,
+                usernames: usernames.into_iter().collect(),
+            });
+        }
+        let members_without_keys = self.members.difference(&contributors).cloned().collect();
+
+        Ok(GroupKeySet {
+            key_type: self.key_type,
+            keys,
+            members_without_keys,
+            hash: hasher.finalize().into(),
+        })
+    }
+}
+
+/// Restores the leading zero bytes `BigUint` drops from fixed-length keys.
+fn fixed_length(bytes: Vec<u8>, len: Option<usize>) -> Vec<u8> {
+    match len {
+        Some(len) if bytes.len() < len => {
+            let mut padded = vec![0u8; len - bytes.len()];
+            padded.extend(bytes);
+            padded
+        }
+        _ => bytes,
+    }
+}
+
+/// A group split by key family, one [`GroupKeySet`] per family that has keys.
+/// Each family is proven with its own circuit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFamilies {
+    members: BTreeSet<String>,
+    sets: BTreeMap<KeyType, GroupKeySet>,
+    skipped: Vec<FetchError>,
+}
+
+impl KeyFamilies {
+    /// Every member of the group, with or without keys.
+    pub fn members(&self) -> impl Iterator<Item = &String> {
+        self.members.iter()
+    }
+
+    /// Families that have at least one key, in [`KeyType`] order.
+    pub fn key_types(&self) -> Vec<KeyType> {
+        self.sets.keys().copied().collect()
+    }
+
+    pub fn get(&self, key_type: KeyType) -> Option<&GroupKeySet> {
+        self.sets.get(&key_type)
+    }
+
+    /// Published keys that were left out of the group, e.g. lines that do
+    /// not parse.
+    pub fn skipped(&self) -> &[FetchError] {
+        &self.skipped
+    }
+
+    /// The key set of `key_type`, or the error naming the members when none
+    /// of them has a key of that family.
+    pub fn into_key_set(mut self, key_type: KeyType) -> Result<GroupKeySet> {
+        if let Some(set) = self.sets.remove(&key_type) {
+            return Ok(set);
+        }
+        let mut builder = GroupKeySetBuilder::new().key_type(key_type);
+        for member in &self.members {
+            builder.add_member(member);
+        }
+        builder.build()
+    }
+}
+
+/// Collects members and keys of any family and splits them into
+/// [`KeyFamilies`].
+#[derive(Debug, Clone, Default)]
+pub struct KeyFamiliesBuilder {
+    members: BTreeSet<String>,
+    keys: BTreeMap<KeyType, Vec<(String, Vec<u8>)>>,
+    skipped: Vec<FetchError>,
+}
+
+impl KeyFamiliesBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a member, even if it ends up contributing no key.
+    pub fn add_member(&mut self, username: &str) -> &mut Self {
+        self.members.insert(username.to_string());
+        self
+    }
+
+    /// Registers a key of family `key_type` published by `username`.
+    pub fn add_key(&mut self, username: &str, key_type: KeyType, public_key: &[u8]) -> &mut Self {
+        self.add_member(username);
+        self.keys
+            .entry(key_type)
+            .or_default()
+            .push((username.to_string(), public_key.to_vec()));
+        self
+    }
+
+    /// Records a published key that was left out of the group.
+    pub fn skip_key(&mut self, err: FetchError) -> &mut Self {
+        self.skipped.push(err);
+        self
+    }
+
+    /// Registers the RSA keys `username` keeps outside of SSH, as PEM blocks,
+    /// a JWK (Set) or OpenSSH lines (see [`import_rsa_public_keys`]).
+    pub fn add_imported_keys(&mut self, username: &str, text: &str) -> Result<&mut Self> {
+        let keys = import_rsa_public_keys(text).map_err(|err| err.for_user(username))?;
+        for key in keys {
+            self.add_key(username, KeyType::Rsa, &key.modulus);
+        }
+        Ok(self)
+    }
+
+    /// Builds one key set per family with keys. Every member is registered
+    /// in every family, so `members_without_keys` is per family. Members
+    /// without any key are only reported by [`KeyFamilies::into_key_set`].
+    pub fn build(self) -> Result<KeyFamilies> {
+        if self.members.is_empty() {
+            return Err(FetchError::NoMembers);
+        }
+        let mut sets: BTreeMap<KeyType, GroupKeySet> = BTreeMap::new();
+        for (key_type, keys) in self.keys {
+            let mut builder = GroupKeySetBuilder::new().key_type(key_type);
+            for member in &self.members {
+                builder.add_member(member);
+            }
+            for (username, public_key) in keys {
+                builder.add_key(&username, &public_key);
+            }
+            sets.insert(key_type, builder.build()?);
+        }
+        Ok(KeyFamilies { members: self.members, sets, skipped: self.skipped })
+    }
+}
+
+fn join(usernames: &BTreeSet<String>) -> String {
+    usernames.iter().cloned().collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedupes_shared_keys_and_sorts_by_modulus() {
+        let mut builder = GroupKeySetBuilder::new();
+        builder
+            .add_key("bob", &[0x02, 0x00])
+            .add_key("alice", &[0x00, 0x03])
+            .add_key("carol", &[0x02, 0x00])
+            .add_member("dave");
+        let set = builder.build().unwrap();
+
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.keys()[0].public_key, vec![0x03]);
+        assert_eq!(set.keys()[1].public_key, vec![0x02, 0x00]);
+        assert_eq!(set.keys()[1].usernames, vec!["bob", "carol"]);
+        assert_eq!(set.members_without_keys(), ["dave".to_string()]);
+        assert_eq!(set.contributions()["carol"], vec![1]);
+    }
+
+    #[test]
+    fn hash_does_not_depend_on_insertion_order() {
+        let mut first = GroupKeySetBuilder::new();
+        first.add_key("alice", &[7]).add_key("bob", &[5]);
+        let mut second = GroupKeySetBuilder::new();
+        second.add_key("bob", &[5]).add_key("alice", &[7]);
+        assert_eq!(first.build().unwrap().hash(), second.build().unwrap().hash());
+    }
+
+    #[test]
+    fn rejects_empty_and_oversized_groups() {
+        let mut empty = GroupKeySetBuilder::new();
+        empty.add_member("alice");
+        let err = empty.build().unwrap_err().to_string();
+        assert!(err.contains("alice"), "{err}");
+
+        let mut large = GroupKeySetBuilder::new().max_size(1);
+        large.add_key("alice", &[1]).add_key("bob", &[2]);
+        assert!(large.build().is_err());
+    }
+
+    #[test]
+    fn partitions_members_by_key_family() {
+        let mut ed25519 = [0u8; 32];
+        ed25519[31] = 7;
+        let mut builder = KeyFamiliesBuilder::new();
+        builder
+            .add_key("alice", KeyType::Rsa, &[9])
+            .add_key("alice", KeyType::Ed25519, &ed25519)
+            .add_key("carol", KeyType::Ed25519, &ed25519)
+            .add_member("dave");
+        let families = builder.build().unwrap();
+
+        assert_eq!(families.key_types(), vec![KeyType::Rsa, KeyType::Ed25519]);
+        let ed25519_set = families.get(KeyType::Ed25519).unwrap();
+        assert_eq!(ed25519_set.keys()[0].public_key, ed25519.to_vec());
+        assert_eq!(ed25519_set.keys()[0].usernames, vec!["alice", "carol"]);
+        assert_eq!(ed25519_set.members_without_keys(), ["dave".to_string()]);
+        assert_eq!(families.get(KeyType::Rsa).unwrap().members_without_keys(), ["carol", "dave"]);
+
+        let err = families.into_key_set(KeyType::EcdsaP256).unwrap_err().to_string();
+        assert!(err.contains("ECDSA P-256") && err.contains("dave"), "{err}");
+    }
+
+    #[test]
+    fn imported_keys_join_the_rsa_family() {
+        let mut builder = KeyFamiliesBuilder::new();
+        builder.add_imported_keys("alice", r#"{"keys":[{"kty":"RSA","n":"CQ","e":"AQAB"}]}"#).unwrap();
+        let set = builder.clone().build().unwrap().into_key_set(KeyType::Rsa).unwrap();
+        assert_eq!(set.keys()[0].public_key, vec![9]);
+
+        let err = builder.add_imported_keys("bob", r#"{"kty":"RSA","n":"CQ","e":"Aw"}"#).unwrap_err().to_string();
+        assert!(err.contains("bob") && err.contains("exponent 3"), "{err}");
+    }
+
+    #[test]
+    fn pads_with_the_first_key() {
+        let mut builder = GroupKeySetBuilder::new();
+        builder.add_key("alice", &[9]);
+        let limbs = builder.build().unwrap().padded_limbs(3).unwrap();
+        assert_eq!(limbs.len(), 3);
+        assert!(limbs.iter().all(|key| key[0] == 9));
+    }
+}
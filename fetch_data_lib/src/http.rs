@@ -0,0 +1,415 @@
+//! HTTP access shared by every key source: one client (with the optional
+//! proxy), conditional requests with ETags, and a memory of rate limits so a
+//! throttled server is left alone until it says requests will succeed again.
+//!
+//! The client is built on the first request, so a TLS backend that fails to
+//! initialise is reported as a [`FetchError`] there rather than a panic.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{HeaderMap, ETAG, IF_NONE_MATCH, RETRY_AFTER, USER_AGENT};
+use reqwest::{Proxy, Response, StatusCode};
+
+use crate::{FetchError, Result};
+
+/// GitHub's API rejects requests without a user agent.
+const CLIENT_USER_AGENT: &str = "send_group_emails";
+
+/// Most responses the ETag cache keeps; the least recently used go first.
+const CACHE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+struct CachedResponse {
+    etag: String,
+    body: String,
+    last_used: u64,
+}
+
+/// Responses by URL, at most `capacity` of them.
+#[derive(Debug)]
+struct ResponseCache {
+    entries: HashMap<String, CachedResponse>,
+    capacity: usize,
+    // counts lookups and inserts, so `last_used` orders the entries
+    clock: u64,
+}
+
+impl ResponseCache {
+    fn new(capacity: usize) -> Self {
+        Self { entries: HashMap::new(), capacity, clock: 0 }
+    }
+
+    fn get(&mut self, url: &str) -> Option<CachedResponse> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(url)?;
+        entry.last_used = self.clock;
+        Some(entry.clone())
+    }
+
+    fn insert(&mut self, url: &str, etag: String, body: String) {
+        self.clock += 1;
+        if !self.entries.contains_key(url)
+            && self.entries.len() >= self.capacity
+            && let Some(oldest) = self.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(url, _)| url.clone())
+        {
+            self.entries.remove(&oldest);
+        }
+        self.entries.insert(url.to_string(), CachedResponse { etag, body, last_used: self.clock });
+    }
+}
+
+/// Point in time before which a host should not be asked again, and the
+/// status that told us so.
+#[derive(Debug, Clone, Copy)]
+struct Throttle {
+    until: SystemTime,
+    status: u16,
+}
+
+/// Clones share the client, the ETag cache and the rate-limit state.
+#[derive(Debug, Clone)]
+pub(crate) struct HttpClient {
+    proxy: Option<Proxy>,
+    client: Arc<OnceLock<Result<reqwest::Client>>>,
+    cache: Arc<Mutex<ResponseCache>>,
+    throttles: Arc<Mutex<HashMap<String, Throttle>>>,
+}
+
+impl HttpClient {
+    /// Uses the system proxy settings (`HTTPS_PROXY`, ...).
+    pub(crate) fn new() -> Self {
+        Self {
+            proxy: None,
+            client: Arc::default(),
+            cache: Arc::new(Mutex::new(ResponseCache::new(CACHE_CAPACITY))),
+            throttles: Arc::default(),
+        }
+    }
+
+    /// `proxy` is a URL such as `http://proxy:3128`.
+    pub(crate) fn with_proxy(proxy: &str) -> Result<Self> {
+        let parsed = Proxy::all(proxy)
+            .map_err(|err| FetchError::invalid_input(format!("Invalid proxy URL '{}': {}", proxy, err)))?;
+        Ok(Self { proxy: Some(parsed), ..Self::new() })
+    }
+
+    fn client(&self) -> Result<reqwest::Client> {
+        self.client
+            .get_or_init(|| {
+                let mut builder = reqwest::Client::builder();
+                if let Some(proxy) = &self.proxy {
+                    builder = builder.proxy(proxy.clone());
+                }
+                builder.build().map_err(|err| FetchError::invalid_input(format!("Cannot build HTTP client: {}", err)))
+            })
+            .clone()
+    }
+
+    /// GETs `url` on behalf of `username`. A cached body is revalidated with
+    /// `If-None-Match` and reused on `304 Not Modified`.
+    pub(crate) async fn get(&self, url: &str, username: &str, headers: HeaderMap) -> Result<String> {
+        let origin = origin(url);
+        if let Some(throttle) = self.throttles.lock().unwrap().get(&origin).copied()
+            && let Ok(remaining) = throttle.until.duration_since(SystemTime::now())
+        {
+            return Err(FetchError::RateLimited {
+                username: username.to_string(),
+                status: throttle.status,
+                retry_after: Some(seconds_rounded_up(remaining)),
+            });
+        }
+
+        let cached = self.cache.lock().unwrap().get(url);
+        let mut request = self.client()?.get(url).header(USER_AGENT, CLIENT_USER_AGENT).headers(headers);
+        if let Some(cached) = &cached {
+            request = request.header(IF_NONE_MATCH, &cached.etag);
+        }
+        let response = request.send().await.map_err(|err| FetchError::Network {
+            username: username.to_string(),
+            reason: err.to_string(),
+        })?;
+
+        let status = response.status();
+        if let Some(until) = throttled_until(&response) {
+            self.throttles.lock().unwrap().insert(origin, Throttle { until, status: status.as_u16() });
+        }
+        if status == StatusCode::NOT_MODIFIED
+            && let Some(cached) = cached
+        {
+            return Ok(cached.body);
+        }
+        if !status.is_success() {
+            return Err(status_error(username, &response));
+        }
+
+        let etag = header(&response, ETAG.as_str());
+        let body = response.text().await.map_err(|err| FetchError::Network {
+            username: username.to_string(),
+            reason: format!("error while downloading keys: {}", err),
+        })?;
+        if let Some(etag) = etag {
+            self.cache.lock().unwrap().insert(url, etag, body.clone());
+        }
+        Ok(body)
+    }
+}
+
+/// Classifies a non-success response: 404 is an unknown user; 429, and 403
+/// once `X-RateLimit-Remaining` hits zero, are rate limits.
+fn status_error(username: &str, response: &Response) -> FetchError {
+    let username = username.to_string();
+    let status = response.status().as_u16();
+    let exhausted = header(response, "x-ratelimit-remaining").as_deref() == Some("0");
+    if status == 429 || (status == 403 && exhausted) {
+        let retry_after = throttled_until(response)
+            .and_then(|until| until.duration_since(SystemTime::now()).ok())
+            .map(seconds_rounded_up);
+        return FetchError::RateLimited { username, status, retry_after };
+    }
+    match status {
+        404 => FetchError::UserNotFound { username },
+        _ => FetchError::Http { username, status },
+    }
+}
+
+/// When the response says no further request will succeed: `Retry-After`
+/// (in seconds), or `X-RateLimit-Reset` once `X-RateLimit-Remaining` is 0.
+fn throttled_until(response: &Response) -> Option<SystemTime> {
+    if let Some(seconds) = header(response, RETRY_AFTER.as_str()).and_then(|value| value.parse::<u64>().ok()) {
+        return Some(SystemTime::now() + Duration::from_secs(seconds));
+    }
+    if header(response, "x-ratelimit-remaining").as_deref() != Some("0") {
+        return None;
+    }
+    let reset = header(response, "x-ratelimit-reset")?.parse::<u64>().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(reset))
+}
+
+fn header(response: &Response, name: &str) -> Option<String> {
+    response.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string)
+}
+
+fn seconds_rounded_up(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// `scheme://host:port` of `url`; rate limits are tracked per origin.
+fn origin(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(url) => url.origin().ascii_serialization(),
+        Err(_) => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{KeyServer, KeySource, KeyType, MembershipSource};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const ED25519: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHUAqyCmL8wF6Vsxr4O/ijvG4ujMKdeAeqGgCNY7AG4y";
+
+    /// Minimal stand-in for api.github.com: records every request head and
+    /// answers with whatever `respond` returns for it.
+    struct StandIn {
+        url: String,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    async fn stand_in(respond: fn(&str) -> String) -> StandIn {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests: Arc<Mutex<Vec<String>>> = Arc::default();
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut head: Vec<u8> = Vec::new();
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).await.unwrap() == 1 {
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8_lossy(&head).to_ascii_lowercase();
+                let response = respond(&head);
+                recorded.lock().unwrap().push(head);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        StandIn { url, requests }
+    }
+
+    fn response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\n{}content-length: {}\r\nconnection: close\r\n\r\n{}",
+            status,
+            headers,
+            body.len(),
+            body
+        )
+    }
+
+    fn api_server(stand_in: &StandIn) -> KeyServer {
+        KeyServer::new("http://github.invalid/")
+            .with_api_url(&stand_in.url)
+            .with_sources(&[KeySource::GitHubApi])
+    }
+
+    #[tokio::test]
+    async fn api_source_sends_token_and_revalidates_with_etag() {
+        let stand_in = stand_in(|head| {
+            if head.contains("if-none-match: \"v1\"") {
+                return response("304 Not Modified", "etag: \"v1\"\r\n", "");
+            }
+            let body = format!(r#"[{{"id":1,"key":"{}"}}]"#, ED25519);
+            response("200 OK", "etag: \"v1\"\r\ncontent-type: application/json\r\n", &body)
+        })
+        .await;
+        let server = api_server(&stand_in).with_token("secret");
+
+        let first = server.fetch_keys("alice").await.unwrap();
+        let second = server.clone().fetch_keys("alice").await.unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(first.keys[0].0, KeyType::Ed25519);
+        let requests = stand_in.requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].starts_with("get /users/alice/keys "), "{}", requests[0]);
+        assert!(requests[0].contains("authorization: bearer secret"));
+        assert!(requests[0].contains("user-agent: send_group_emails"));
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+        assert!(!format!("{:?}", server).contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn exhausted_rate_limit_is_honored_without_asking_again() {
+        let stand_in = stand_in(|_| {
+            let reset = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 120;
+            let headers = format!("x-ratelimit-remaining: 0\r\nx-ratelimit-reset: {}\r\n", reset);
+            response("403 Forbidden", &headers, "API rate limit exceeded")
+        })
+        .await;
+        let server = api_server(&stand_in);
+
+        for _ in 0..2 {
+            match server.fetch_keys("alice").await.unwrap_err() {
+                FetchError::RateLimited { status: 403, retry_after: Some(seconds), .. } => {
+                    assert!((119..=121).contains(&seconds), "{seconds}")
+                }
+                other => panic!("unexpected error: {other:?}"),
+            }
+        }
+        assert_eq!(stand_in.requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_after_and_missing_users_are_classified() {
+        let stand_in = stand_in(|head| match head.starts_with("get /users/busy/") {
+            true => response("429 Too Many Requests", "retry-after: 30\r\n", ""),
+            false => response("404 Not Found", "", r#"{"message":"Not Found"}"#),
+        })
+        .await;
+
+        let err = api_server(&stand_in).fetch_keys("ghost").await.unwrap_err();
+        assert_eq!(err, FetchError::UserNotFound { username: "ghost".into() });
+        let err = api_server(&stand_in).fetch_keys("busy").await.unwrap_err();
+        assert!(matches!(err, FetchError::RateLimited { status: 429, retry_after: Some(30), .. }), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn requests_go_through_the_proxy() {
+        let proxy = stand_in(|_| response("200 OK", "", &format!(r#"[{{"id":1,"key":"{}"}}]"#, ED25519))).await;
+        let server = KeyServer::new("http://github.invalid/")
+            .with_api_url("http://api.github.invalid/")
+            .with_sources(&[KeySource::GitHubApi])
+            .with_proxy(&proxy.url)
+            .unwrap();
+
+        assert_eq!(server.fetch_keys("alice").await.unwrap().keys.len(), 1);
+        let requests = proxy.requests.lock().unwrap().clone();
+        assert!(requests[0].starts_with("get http://api.github.invalid/users/alice/keys "), "{}", requests[0]);
+        assert!(KeyServer::default().with_proxy("not a url").is_err());
+    }
+
+    #[test]
+    fn cache_drops_the_least_recently_used_response() {
+        let mut cache = ResponseCache::new(2);
+        cache.insert("a", "\"1\"".into(), "alice".into());
+        cache.insert("b", "\"2\"".into(), "bob".into());
+        assert!(cache.get("a").is_some());
+        cache.insert("c", "\"3\"".into(), "carol".into());
+
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cache.get("b").is_none());
+        assert_eq!(cache.get("a").unwrap().body, "alice");
+        // replacing an entry does not evict another
+        cache.insert("c", "\"4\"".into(), "carol".into());
+        assert!(cache.get("a").is_some() && cache.get("c").is_some());
+    }
+
+    #[tokio::test]
+    async fn unusable_lines_are_skipped_and_reported() {
+        let stand_in = stand_in(|_| {
+            let body = format!(
+                "{}not a key\n{}{}\n",
+                include_str!("../fixtures/alice.pub"),
+                include_str!("../fixtures/alice-cert.pub"),
+                ED25519
+            );
+            response("200 OK", "", &body)
+        })
+        .await;
+
+        let fetched = KeyServer::new(&stand_in.url).fetch_keys("alice").await.unwrap();
+        let key_types: Vec<KeyType> = fetched.keys.iter().map(|(key_type, _)| *key_type).collect();
+        assert_eq!(key_types, [KeyType::Rsa, KeyType::Ed25519]);
+        let skipped: Vec<(Option<String>, Option<usize>)> = fetched
+            .skipped
+            .iter()
+            .map(|err| match err {
+                FetchError::MalformedKey { username, key_index, .. } => (username.clone(), *key_index),
+                other => panic!("unexpected error: {other:?}"),
+            })
+            .collect();
+        assert_eq!(skipped, [(Some("alice".into()), Some(2)), (Some("alice".into()), Some(3))]);
+        assert!(fetched.skipped[1].to_string().contains("certificates are not accepted"), "{}", fetched.skipped[1]);
+    }
+
+    #[tokio::test]
+    async fn certificates_are_refused_by_every_entry_point() {
+        let stand_in = stand_in(|_| {
+            let body = format!("{}{}", include_str!("../fixtures/alice.pub"), include_str!("../fixtures/alice-cert.pub"));
+            response("200 OK", "", &body)
+        })
+        .await;
+        let document = format!("{}{}", include_str!("../fixtures/alice.pub"), include_str!("../fixtures/alice-cert.pub"));
+
+        let fetched = KeyServer::new(&stand_in.url).fetch_keys("alice").await.unwrap();
+        assert_eq!(fetched.keys.len(), 1);
+        let keys = crate::parce_keys(&document).await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].starts_with("ssh-rsa "), "{}", keys[0]);
+        let err = crate::extract_rsa_from_ssh(include_str!("../fixtures/alice-cert.pub")).await.unwrap_err();
+        assert!(err.to_string().contains("ssh-rsa certificates are not accepted"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn member_listings_are_not_cut_short() {
+        let stand_in = stand_in(|_| {
+            let members: Vec<String> = (0..100).map(|index| format!(r#"{{"login":"user{}"}}"#, index)).collect();
+            response("200 OK", "", &format!("[{}]", members.join(",")))
+        })
+        .await;
+
+        let err = api_server(&stand_in).org_members("huge").await.unwrap_err();
+        assert_eq!(err, FetchError::MembershipTooLarge { group: "org:huge".into(), max: 1000 });
+        assert_eq!(stand_in.requests.lock().unwrap().len(), 10);
+        // usernames are checked before they reach a URL
+        let err = api_server(&stand_in).fetch_keys("../orgs/huge/members").await.unwrap_err();
+        assert!(matches!(err, FetchError::InvalidInput { .. }), "{err:?}");
+        assert_eq!(stand_in.requests.lock().unwrap().len(), 10);
+    }
+}
@@ -0,0 +1,175 @@
+//! Key families the group can be proven over. Every family has its own
+//! circuit (and verifying key), so a group is split by family and each part
+//! is padded and limbed on its own.
+//!
+//! Key material is kept as bytes in the form the family's circuit reads it:
+//!
+//! * RSA – the big-endian modulus, 35 limbs of 120 bits (`GroupSignature`).
+//! * Ed25519 – the 32-byte RFC 8032 encoding of the point, read as a
+//!   little-endian integer and split into 4 limbs of 64 bits.
+//! * ECDSA P-256 – `X || Y` (32 big-endian bytes each), as the `pubkey[2][4]`
+//!   input of circom-ecdsa: 4 limbs of 64 bits for `X`, then 4 for `Y`.
+//!
+//! Only the RSA layout is checked by a circuit in this repository. The
+//! Ed25519 and P-256 layouts are provisional, and the message is still packed
+//! as for RSA (SHA-512 in 5 limbs of 120 bits); the server only accepts those
+//! families when built with its `experimental-key-families` feature.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use num_bigint::BigUint;
+
+use crate::{convert_byte_to_chunks, modulus_to_limbs, FetchError, Result};
+
+/// The RSA public exponent the `GroupSignature` circuit hard-codes. Keys with
+/// any other exponent cannot be proven over, and are refused when fetched so
+/// that a key's modulus alone identifies it (see [`crate::key_fingerprint`]).
+pub const RSA_EXPONENT: u32 = 65537;
+
+/// Refuses a big-endian RSA exponent other than [`RSA_EXPONENT`].
+pub fn check_rsa_exponent(exponent: &[u8]) -> Result<()> {
+    if BigUint::from_bytes_be(exponent) != BigUint::from(RSA_EXPONENT) {
+        return Err(FetchError::malformed(format!(
+            "Unsupported RSA exponent {}: the circuit only accepts {}",
+            BigUint::from_bytes_be(exponent),
+            RSA_EXPONENT
+        )));
+    }
+    Ok(())
+}
+
+/// Family of a member key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+pub enum KeyType {
+    #[default]
+    #[serde(rename = "rsa")]
+    Rsa,
+    #[serde(rename = "ed25519")]
+    Ed25519,
+    #[serde(rename = "p256")]
+    EcdsaP256,
+}
+
+impl KeyType {
+    pub const ALL: [KeyType; 3] = [KeyType::Rsa, KeyType::Ed25519, KeyType::EcdsaP256];
+
+    /// Family of an OpenSSH key type (certificate suffix already removed).
+    /// Security-key (`sk-`) variants sign a FIDO envelope rather than the
+    /// message, so they have no family.
+    pub fn from_ssh_key_type(key_type: &str) -> Option<KeyType> {
+        match key_type {
+            "ssh-rsa" => Some(KeyType::Rsa),
+            "ssh-ed25519" => Some(KeyType::Ed25519),
+            "ecdsa-sha2-nistp256" => Some(KeyType::EcdsaP256),
+            _ => None,
+        }
+    }
+
+    /// Short name used in configuration and submissions.
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyType::Rsa => "rsa",
+            KeyType::Ed25519 => "ed25519",
+            KeyType::EcdsaP256 => "p256",
+        }
+    }
+
+    /// Exact length of the key material, if the family has a fixed one.
+    pub fn key_len(&self) -> Option<usize> {
+        match self {
+            KeyType::Rsa => None,
+            KeyType::Ed25519 => Some(32),
+            KeyType::EcdsaP256 => Some(64),
+        }
+    }
+
+    /// Number of limbs one key occupies in the `keys` public input.
+    pub fn limbs_per_key(&self) -> usize {
+        match self {
+            KeyType::Rsa => 35,
+            KeyType::Ed25519 => 4,
+            KeyType::EcdsaP256 => 8,
+        }
+    }
+
+    /// Splits key material into the limbs of this family's circuit.
+    pub fn key_to_limbs(&self, key: &[u8]) -> Result<Vec<u128>> {
+        if let Some(len) = self.key_len()
+            && key.len() != len
+        {
+            return Err(FetchError::malformed(format!("Invalid {} key: expected {} bytes, got {}", self, len, key.len())));
+        }
+        match self {
+            KeyType::Rsa => modulus_to_limbs(key),
+            KeyType::Ed25519 => {
+                let big_endian: Vec<u8> = key.iter().rev().copied().collect();
+                convert_byte_to_chunks(64, 4, &big_endian)
+            }
+            KeyType::EcdsaP256 => {
+                let mut limbs = convert_byte_to_chunks(64, 4, &key[..32])?;
+                limbs.extend(convert_byte_to_chunks(64, 4, &key[32..])?);
+                Ok(limbs)
+            }
+        }
+    }
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyType::Rsa => write!(f, "RSA"),
+            KeyType::Ed25519 => write!(f, "Ed25519"),
+            KeyType::EcdsaP256 => write!(f, "ECDSA P-256"),
+        }
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = FetchError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "rsa" => Ok(KeyType::Rsa),
+            "ed25519" => Ok(KeyType::Ed25519),
+            "p256" | "p-256" | "ecdsa-p256" => Ok(KeyType::EcdsaP256),
+            other => Err(FetchError::invalid_input(format!(
+                "Unknown key type '{}': expected 'rsa', 'ed25519' or 'p256'",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limbs_follow_the_circuit_layouts() {
+        let mut ed25519 = [0u8; 32];
+        ed25519[0] = 1;
+        ed25519[8] = 2;
+        assert_eq!(KeyType::Ed25519.key_to_limbs(&ed25519).unwrap(), vec![1, 2, 0, 0]);
+
+        let mut p256 = [0u8; 64];
+        p256[31] = 3;
+        p256[32] = 0x80;
+        assert_eq!(
+            KeyType::EcdsaP256.key_to_limbs(&p256).unwrap(),
+            vec![3, 0, 0, 0, 0, 0, 0, 0x80 << 56]
+        );
+        assert!(KeyType::Ed25519.key_to_limbs(&p256).is_err());
+    }
+
+    #[test]
+    fn parses_names() {
+        for key_type in KeyType::ALL {
+            assert_eq!(key_type.name().parse::<KeyType>().unwrap(), key_type);
+        }
+        assert_eq!(serde_json::to_string(&KeyType::EcdsaP256).unwrap(), "\"p256\"");
+        assert!("dsa".parse::<KeyType>().is_err());
+    }
+}
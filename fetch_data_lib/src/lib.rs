@@ -1,32 +1,54 @@
-//! Utility functions for fetching GitHub users' RSA public keys and constructing
+//! Utility functions for fetching GitHub users' public keys and constructing
 //! the `publicSignals` array expected by the Circom/zk-SNARK circuit. All
 //! helpers are `async` where network or heavy computation is involved and can
 //! therefore be called inside an asynchronous runtime (e.g. Tokio).
 
-use reqwest::get;
 use num_traits::cast::ToPrimitive;
 use num_bigint::BigUint;
-use serde_json::Value;
-use std::{char::MAX, io};
-use anyhow::{anyhow, Result};
-use sha2::{Sha256, Sha512, Digest};
 use serde::{Serialize, Deserialize};
+use reqwest::header::{HeaderMap, HeaderValue};
 
-use base64::decode;
-use std::error::Error;
+pub mod error;
+pub use error::{FetchError, Result};
+mod http;
+pub mod key_type;
+pub use key_type::{check_rsa_exponent, KeyType, RSA_EXPONENT};
+pub mod blocklist;
+pub use blocklist::{key_fingerprint, BlockedKey, Blocklist};
+pub mod group;
+pub use group::{GroupKey, GroupKeySet, GroupKeySetBuilder, KeyFamilies, KeyFamiliesBuilder};
+pub mod openssh;
+pub use openssh::{parse_public_key_line, parse_public_keys, ParsedKeys, PublicKey, UnsupportedLine};
+pub mod rsa_import;
+pub use rsa_import::{import_rsa_public_keys, rsa_from_jwk, rsa_from_pkcs1_pem, rsa_from_spki_pem, RsaComponents};
+pub mod openpgp;
+pub use openpgp::{parse_openpgp_rsa_keys, parse_openpgp_rsa_keys_at, OpenPgpRsaKey};
+pub mod membership;
+pub use membership::{apply_membership_policy, MembershipOutcome, MembershipPolicy};
+pub mod roster;
+pub use roster::{checked_username, resolve_senders, MembersFuture, MembershipSource, SenderRef};
+pub mod message;
+pub use message::{CanonicalMessage, MESSAGE_DOMAIN_TAG};
+pub mod sshsig;
+pub use sshsig::{sign_ssh_signature, verify_ssh_signature};
+pub mod witness;
+pub use witness::{build_circuit_input, build_circuit_input_from_signature, load_private_key_pem, CircuitInput};
+#[cfg(unix)]
+pub mod ssh_agent;
+#[cfg(unix)]
+pub use ssh_agent::{build_circuit_input_with_agent, AgentIdentity, SshAgentClient};
 
-const BLOCK_SIZE :usize = 35;
-const MAX_GROUP_SIZE : usize = 300;
+pub const MAX_GROUP_SIZE : usize = 300;
 
 /// Represents the data that will be passed to the circuit as `publicSignals`.
 ///
 /// Fields
 /// -------
-/// * `message_hash` – SHA-512 digest of the message split into five 120-bit
-///   limbs.
-/// * `keys` – A collection of **exactly** `MAX_GROUP_SIZE` RSA public keys
-///   (padded if necessary). Each key is itself split into 35 limbs of
-///   120-bit width.
+/// * `message_hash` – SHA-512 digest of the canonical message encoding (see
+///   [`CanonicalMessage`]) split into five 120-bit limbs.
+/// * `keys` – A collection of **exactly** `MAX_GROUP_SIZE` public keys of one
+///   [`KeyType`] (padded if necessary). Each key is itself split into the
+///   limbs of its family, e.g. 35 limbs of 120-bit width for RSA.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PublicSignals{
 
@@ -35,6 +57,12 @@ pub struct PublicSignals{
     
 }
 
+impl Default for PublicSignals{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
 impl PublicSignals{
     pub fn new() -> Self{
         Self{
@@ -51,209 +79,381 @@ impl PublicSignals{
 ///
 /// Returns an error if the integer does not fit into the provided number of
 /// chunks.
-async fn convert_byte_to_chunks(num_bits: u32, num_chunks: u32, array: Vec<u8>) -> anyhow::Result<Vec<u128>>{
-    let mut big_int : BigUint = BigUint::from_bytes_be(array[..].try_into().unwrap());
+pub(crate) fn convert_byte_to_chunks(num_bits: u32, num_chunks: u32, array: &[u8]) -> Result<Vec<u128>>{
+    let mut big_int : BigUint = BigUint::from_bytes_be(array);
     let mut res : Vec<u128> = Vec::new();
-    for i in 0 .. num_chunks {
-        let curr : u128 = (big_int.clone() % (1u128 << num_bits.clone())).to_u128().unwrap();
+    for _ in 0 .. num_chunks {
+        let curr : u128 = (big_int.clone() % (1u128 << num_bits)).to_u128().unwrap();
         res.push(curr);
-        big_int = big_int >> num_bits.clone();
+        big_int >>= num_bits;
     }
     // make sure that num_chunks is enough to cover the whole number
     if big_int != BigUint::from(0u32) {
-        return Err(anyhow!(
+        return Err(FetchError::invalid_input(format!(
             "Cannot convert number: value does not fit into {} chunks of {} bits",
             num_chunks,
             num_bits
-        ));
+        )));
     }
     Ok(res)
 }
 
+/// Splits a big-endian RSA modulus into the 35 little-endian 120-bit limbs the
+/// circuit uses for `keys` and `correctKey`. Every key source goes through this.
+pub fn modulus_to_limbs(modulus: &[u8]) -> Result<Vec<u128>>{
+    convert_byte_to_chunks(120, 35, modulus).map_err(|err| FetchError::malformed(format!("RSA modulus is too large: {}", err)))
+}
+
 /// Parses an SSH-formatted RSA public key (the `ssh-rsa AAAAB3...` string) and
 /// extracts the modulus `n` and exponent `e` in raw big-endian byte form.
 ///
-/// The function performs basic validation on the key structure and returns
-/// detailed errors when the format is unexpected.
-pub async fn extract_rsa_from_ssh(ssh_key: &str) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
-    let parts: Vec<&str> = ssh_key.trim().split_whitespace().collect();
-    if parts.len() < 2 {
-        return Err(anyhow!("Invalid SSH key format: should include key type"));
+/// Built on [`parse_public_key_line`], so `authorized_keys` options and
+/// comments are accepted; certificates, as in [`KeyServer::fetch_keys`], and
+/// every other key type are an error.
+pub async fn extract_rsa_from_ssh(ssh_key: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+    match parse_public_key_line(ssh_key)? {
+        Some(key) => {
+            check_not_certificate(&key)?;
+            key.rsa_components()
+        }
+        None => Err(FetchError::malformed("Invalid SSH key format: should include key type")),
     }
+}
 
-    // Validate key type
-    if !parts[0].starts_with("ssh-rsa") {
-        return Err(anyhow!("Unsupported key type: {}", parts[0]));
+/// Splits the body returned by GitHub's `https://github.com/<user>.keys` API
+/// into individual *RSA* keys (certificates and other key types are ignored,
+/// as in [`KeyServer::fetch_keys`]). Lines that are not valid public keys are
+/// reported as an error; use [`parse_public_keys`] to get typed keys and the
+/// unsupported lines instead.
+pub async fn parce_keys(all_data: &str) -> Result<Vec<String>>{
+    let parsed = parse_public_keys(all_data);
+    if let Some(line) = parsed.unsupported.first() {
+        return Err(FetchError::MalformedKey{
+            username: None,
+            key_index: Some(line.line_number),
+            reason: format!("unexpected formatting: {}", line.reason),
+        });
     }
-    let key_data = decode(parts[1])?;
-    let mut offset = 0;
+    Ok(parsed.rsa_keys().filter(|key| !key.is_certificate()).map(|key| key.to_openssh() + "\n").collect())
+}
 
-    // Helper function to read a u32 length-prefixed field
-    async fn read_length(data: &[u8], offset: &mut usize) -> anyhow::Result<usize> {
-        if *offset + 4 > data.len() {
-            return Err(anyhow!("Unexpected end of data when reading length"));
-        }
-        let len = u32::from_be_bytes(data[*offset..*offset + 4].try_into()?) as usize;
-        *offset += 4;
-        
-        // Validate length is reasonable
-        if len > data.len() - *offset {
-            return Err(anyhow!("Invalid length {}: exceeds remaining data", len));
+// a certificate's CA is never checked, so only plain keys are taken from any source
+fn check_not_certificate(key: &PublicKey) -> Result<()> {
+    match key.is_certificate() {
+        true => Err(FetchError::malformed(format!("{} certificates are not accepted", key.base_key_type()))),
+        false => Ok(()),
+    }
+}
+
+/// Base URL of the server publishing `<user>.keys` files.
+pub const GITHUB_BASE_URL: &str = "https://github.com/";
+
+/// Base URL of the GitHub REST API.
+pub const GITHUB_API_URL: &str = "https://api.github.com/";
+
+/// A per-user document the key server publishes keys in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySource{
+    /// OpenSSH keys at `<base_url><user>.keys`.
+    Ssh,
+    /// Armored OpenPGP keyring at `<base_url><user>.gpg`.
+    Gpg,
+    /// SSH keys from the REST API at `<api_url>users/<user>/keys`, which
+    /// accepts a token and has a far higher rate limit when given one.
+    GitHubApi,
+}
+
+impl std::str::FromStr for KeySource{
+    type Err = FetchError;
+
+    fn from_str(value: &str) -> Result<Self>{
+        match value.trim().to_ascii_lowercase().as_str(){
+            "ssh" => Ok(KeySource::Ssh),
+            "gpg" | "pgp" => Ok(KeySource::Gpg),
+            "api" | "github-api" => Ok(KeySource::GitHubApi),
+            other => Err(FetchError::invalid_input(format!("Unknown key source '{}': expected 'ssh', 'gpg' or 'api'", other))),
         }
-        if len == 0 {
-            return Err(anyhow!("Invalid length: zero length field"));
+    }
+}
+
+/// Where members' public keys are downloaded from. Defaults to GitHub; tests
+/// and self-hosted deployments point it at another server with the same
+/// `<base_url><user>.keys` layout.
+///
+/// Clones share one HTTP client, so they also share its ETag cache and what
+/// it learned about rate limits.
+#[derive(Clone)]
+pub struct KeyServer{
+    base_url: String,
+    api_url: String,
+    token: Option<String>,
+    sources: Vec<KeySource>,
+    http: http::HttpClient,
+}
+
+impl std::fmt::Debug for KeyServer{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result{
+        f.debug_struct("KeyServer")
+            .field("base_url", &self.base_url)
+            .field("api_url", &self.api_url)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field("sources", &self.sources)
+            .finish()
+    }
+}
+
+impl Default for KeyServer{
+    fn default() -> Self{
+        Self::new(GITHUB_BASE_URL)
+    }
+}
+
+impl KeyServer{
+    /// `base_url` is used as a prefix; a missing trailing `/` is added. Only
+    /// SSH keys are fetched until [`KeyServer::with_sources`] says otherwise.
+    pub fn new(base_url: &str) -> Self{
+        Self{
+            base_url: with_trailing_slash(base_url),
+            api_url: GITHUB_API_URL.to_string(),
+            token: None,
+            sources: vec![KeySource::Ssh],
+            http: http::HttpClient::new(),
         }
-        
-        Ok(len)
     }
 
-    // Skip key type
-    let key_type_len = read_length(&key_data, &mut offset).await?;
-    
-    // Validate key type string
-    if key_type_len > key_data.len() - offset {
-        return Err(anyhow!("Key type length exceeds data length"));
+    /// Overrides the REST API base URL used by [`KeySource::GitHubApi`].
+    pub fn with_api_url(mut self, api_url: &str) -> Self{
+        self.api_url = with_trailing_slash(api_url);
+        self
     }
-    let key_type = String::from_utf8_lossy(&key_data[offset..offset + key_type_len]);
-    if key_type != "ssh-rsa" {
-        return Err(anyhow!("Invalid key type in data: {}", key_type));
+
+    /// Token sent as `Authorization: Bearer` to the REST API (never to
+    /// `base_url`).
+    pub fn with_token(mut self, token: &str) -> Self{
+        self.token = Some(token.to_string());
+        self
     }
-    offset += key_type_len;
 
-    // Read e (exponent)
-    let e_len = read_length(&key_data, &mut offset).await?;
-    if e_len > key_data.len() - offset {
-        return Err(anyhow!("Exponent length exceeds data length"));
+    /// Sends every request through the proxy at `proxy_url`, e.g.
+    /// `http://proxy:3128`. Without it the system proxy settings apply.
+    pub fn with_proxy(mut self, proxy_url: &str) -> Result<Self>{
+        self.http = http::HttpClient::with_proxy(proxy_url)?;
+        Ok(self)
     }
-    let e = key_data[offset..offset + e_len].to_vec();
-    offset += e_len;
 
-    // Read n (modulus)
-    let n_len = read_length(&key_data, &mut offset).await?;
-    if n_len > key_data.len() - offset {
-        return Err(anyhow!("Modulus length exceeds data length"));
+    /// Selects the documents keys are collected from, e.g. SSH and GPG.
+    pub fn with_sources(mut self, sources: &[KeySource]) -> Self{
+        self.sources = sources.to_vec();
+        self
     }
-    let mut n = key_data[offset..offset + n_len].to_vec();
-    
-    // Remove leading zero if present (SSH sometimes encodes n with a leading 0)
-    if !n.is_empty() && n[0] == 0x00 {
-        n = n[1..].to_vec();
+
+    pub fn base_url(&self) -> &str{
+        &self.base_url
     }
 
-    // Validate that we've consumed all data
-    if offset + n_len != key_data.len() {
-        return Err(anyhow!("Extra data after modulus"));
+    pub fn api_url(&self) -> &str{
+        &self.api_url
     }
 
-    Ok((n, e))
-}
+    pub fn sources(&self) -> &[KeySource]{
+        &self.sources
+    }
 
-/// Splits the body returned by GitHub's `https://github.com/<user>.keys` API
-/// into individual *RSA* keys (other key types are ignored).
-pub async fn parce_keys(all_data: &str) -> anyhow::Result<Vec<String>>{
-    let mut key_list: Vec<&str> = all_data.trim().split("ssh-").collect();
-    let mut result : Vec<String> = Vec::new();
-    for key in key_list{
-        if key != ""{
-            let mut parts: Vec<&str> = key.trim().split_whitespace().collect();
-            if parts.len() < 2 {
-                return Err(anyhow!(
-                    "Unable to parse GitHub SSH keys: unexpected formatting detected"
-                ));
-            }
-            if parts[0].starts_with("rsa") {
-                let mut key = "ssh-rsa ".to_owned() + parts[1] + "\n";
-                result.push(key.to_string());
-            }
+    /// Headers of every GitHub REST API request, with the token if one is set.
+    fn api_headers(&self) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert("accept", HeaderValue::from_static("application/vnd.github+json"));
+        headers.insert("x-github-api-version", HeaderValue::from_static("2022-11-28"));
+        if let Some(token) = &self.token {
+            let value = HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|_| FetchError::invalid_input("GitHub token contains invalid characters"))?;
+            headers.insert("authorization", value);
         }
+        Ok(headers)
     }
-    Ok(result)
-}
 
-/// Downloads all RSA public keys of a GitHub user, extracts their moduli and
-/// converts them into 120-bit limb representation suitable for the circuit.
-pub async fn get_and_process_username(username : String) -> anyhow::Result<Vec<Vec<u128>>> {
-    let address = format!("{}{}{}", "https://github.com/", username, ".keys");
-    let mut result : Vec<Vec<u128>> = Vec::new();
-    match get(&address).await {
-        Ok(response) => {
-            if response.status().is_success() {
-                match response.text().await {
-                    Ok(body) => {
-                        let mut list_keys = parce_keys(&body).await?;
-                        for key in list_keys{
-                            let extracted_key = extract_rsa_from_ssh(&key).await?;
-                            let mut convert = match convert_byte_to_chunks(120, 35, extracted_key.0).await {
-                                Ok(body) => body ,
-                                Err(err) => {
-                                    return Err(anyhow!(
-                                        "Failed to convert key chunks for user '{}': {}",
-                                        username,
-                                        err
-                                    ))
-                                },
-                            };
-
-                            result.push(convert);
+    async fn fetch_document(&self, username: &str, source: KeySource) -> Result<String> {
+        let username = checked_username(username)?;
+        let (address, headers) = match source {
+            KeySource::Ssh => (format!("{}{}.keys", self.base_url, username), HeaderMap::new()),
+            KeySource::Gpg => (format!("{}{}.gpg", self.base_url, username), HeaderMap::new()),
+            KeySource::GitHubApi => (format!("{}users/{}/keys", self.api_url, username), self.api_headers()?),
+        };
+        self.http.get(&address, username, headers).await
+    }
+
+    /// Downloads all RSA public keys of a user from every configured source
+    /// and returns their moduli as raw big-endian bytes.
+    pub async fn fetch_moduli(&self, username: &str) -> Result<Vec<Vec<u8>>> {
+        let keys = self.fetch_keys(username).await?.keys;
+        Ok(keys.into_iter().filter(|(key_type, _)| *key_type == KeyType::Rsa).map(|(_, modulus)| modulus).collect())
+    }
+
+    /// Downloads every public key of a user that belongs to a [`KeyType`]
+    /// family, together with its key material. OpenPGP keyrings only
+    /// contribute RSA keys.
+    ///
+    /// A key that cannot be used is skipped and reported in
+    /// [`FetchedKeys::skipped`], with the line (or API entry) number as
+    /// `key_index`; the user's other keys still count. Only a document that
+    /// cannot be downloaded or read as a whole fails the user.
+    ///
+    /// OpenSSH certificates are skipped as well: their CA signature, validity
+    /// period and principals are not checked, so taking the certified key
+    /// would trust whatever certificate a user chose to publish.
+    pub async fn fetch_keys(&self, username: &str) -> Result<FetchedKeys> {
+        let mut result = FetchedKeys::default();
+        for source in &self.sources {
+            let body = self.fetch_document(username, *source).await?;
+            match source {
+                KeySource::Ssh => {
+                    for (index, line) in body.lines().enumerate() {
+                        result.add_ssh_line(username, index + 1, line);
+                    }
+                }
+                KeySource::GitHubApi => {
+                    // [{"id": 1, "key": "ssh-rsa AAAA..."}, ...]
+                    let entries: Vec<serde_json::Value> = serde_json::from_str(&body)
+                        .map_err(|err| FetchError::malformed(format!("Invalid GitHub API response: {}", err)).for_user(username))?;
+                    for (index, entry) in entries.iter().enumerate() {
+                        match entry.get("key").and_then(serde_json::Value::as_str) {
+                            Some(line) if !line.trim().is_empty() => result.add_ssh_line(username, index + 1, line),
+                            _ => result.skipped.push(FetchError::malformed("entry without a key").for_user(username).at_key(index + 1)),
+                        }
+                    }
+                }
+                KeySource::Gpg => {
+                    for (index, key) in parse_openpgp_rsa_keys(&body).map_err(|err| err.for_user(username))?.into_iter().enumerate() {
+                        match check_rsa_exponent(&key.exponent) {
+                            Ok(()) => result.keys.push((KeyType::Rsa, key.modulus)),
+                            Err(err) => result.skipped.push(err.for_user(username).at_key(index + 1)),
                         }
-                        return Ok(result);
-                    },
-                    Err(err) =>{
-                        return Err(anyhow!(
-                            "Error while downloading keys for user '{}': {}",
-                            username,
-                            err
-                        ));
                     }
                 }
-            } else {
-                return Err(anyhow!(
-                    "GitHub returned a non-success status code for user '{}'",
-                    username
-                ));
             }
-
         }
-        Err(err) => {
-            return Err(anyhow!("HTTP request error: {}", err));
+        Ok(result)
+    }
+
+    /// Fetches the keys of every listed member and assembles the RSA ones
+    /// into a deduplicated, canonically ordered [`GroupKeySet`].
+    pub async fn fetch_group_key_set(&self, list_usernames: Vec<String>) -> Result<GroupKeySet>{
+        self.fetch_key_families(list_usernames).await?.into_key_set(KeyType::Rsa)
+    }
+
+    /// Fetches the keys of every listed member and splits the group into one
+    /// key set per [`KeyType`]. Duplicate usernames are fetched only once.
+    pub async fn fetch_key_families(&self, list_usernames: Vec<String>) -> Result<KeyFamilies>{
+        let mut sorted_usernames: Vec<String> = list_usernames;
+        sorted_usernames.sort();
+        sorted_usernames.dedup();
+        let mut builder = KeyFamiliesBuilder::new();
+        for username in sorted_usernames{
+            builder.add_member(&username);
+            let fetched = self.fetch_keys(&username).await?;
+            for (key_type, public_key) in fetched.keys {
+                builder.add_key(&username, key_type, &public_key);
+            }
+            for err in fetched.skipped {
+                builder.skip_key(err);
+            }
         }
+        builder.build()
     }
 }
 
-/// High-level helper that, given a list of GitHub usernames and a plain-text
-/// `message`, constructs a fully-populated `PublicSignals` instance ready for
-/// proof generation.
-pub async fn create_pb_signals_struct(list_usernames: Vec<String>, message: &str) -> anyhow::Result<PublicSignals>{
-    let mut hasher = Sha512::new();
-    hasher.update(message.as_bytes());
-    let mut message_hash = match convert_byte_to_chunks(120, 5, hasher.finalize().to_vec()).await{
-        Ok(body) => body,
-        Err(_err) => return Err(anyhow!("Error processing message. Please ensure that message is a string of ASCII characters.")),
-    };
-    let mut result = PublicSignals::new();
-    result.message_hash = message_hash;
-    let mut sorted_usernames: Vec<String> = list_usernames.clone();
-    sorted_usernames.sort();
-    for username in sorted_usernames{
-        let keys = get_and_process_username(username.clone()).await?;
-        for key in keys{
-            result.keys.push(key);
+/// The keys [`KeyServer::fetch_keys`] found for a user, and the ones it had
+/// to leave out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FetchedKeys{
+    pub keys: Vec<(KeyType, Vec<u8>)>,
+    /// One [`FetchError::MalformedKey`] per key that was left out, naming
+    /// the user and the key's position.
+    pub skipped: Vec<FetchError>,
+}
+
+impl FetchedKeys{
+    /// Adds the key on OpenSSH line `key_index`, or records why it is left
+    /// out. Blank lines, comments and key types of no family are ignored.
+    fn add_ssh_line(&mut self, username: &str, key_index: usize, line: &str){
+        let key = match parse_public_key_line(line) {
+            Ok(Some(key)) => key,
+            Ok(None) => return,
+            Err(err) => return self.skipped.push(err.for_user(username).at_key(key_index)),
+        };
+        if KeyType::from_ssh_key_type(key.base_key_type()).is_none() {
+            return;
+        }
+        if let Err(err) = check_not_certificate(&key) {
+            return self.skipped.push(err.for_user(username).at_key(key_index));
+        }
+        match key.key_material() {
+            Ok(material) => self.keys.push(material),
+            Err(err) => self.skipped.push(err.for_user(username).at_key(key_index)),
         }
     }
-    if result.keys.len() > MAX_GROUP_SIZE {
-        return Err(anyhow!(
-            "Too many keys in the group: maximum allowed is {}",
-            MAX_GROUP_SIZE
-        ));
-    }  
-    while (result.keys.len() < MAX_GROUP_SIZE){
-        result.keys.push(result.keys[0].clone());
+}
+
+fn with_trailing_slash(url: &str) -> String{
+    let mut url = url.to_string();
+    if !url.ends_with('/'){
+        url.push('/');
     }
-    return Ok(result);
+    url
+}
+
+/// Downloads all RSA public keys of a GitHub user and returns their moduli as
+/// raw big-endian bytes.
+pub async fn get_username_moduli(username: &str) -> Result<Vec<Vec<u8>>> {
+    KeyServer::default().fetch_moduli(username).await
+}
+
+/// Downloads all RSA public keys of a GitHub user, extracts their moduli and
+/// converts them into 120-bit limb representation suitable for the circuit.
+pub async fn get_and_process_username(username : String) -> Result<Vec<Vec<u128>>> {
+    let mut result : Vec<Vec<u128>> = Vec::new();
+    for (index, modulus) in get_username_moduli(&username).await?.into_iter().enumerate() {
+        let convert = match modulus_to_limbs(&modulus) {
+            Ok(body) => body ,
+            Err(err) => return Err(err.for_user(&username).at_key(index + 1)),
+        };
+        result.push(convert);
+    }
+    Ok(result)
+}
+
+/// Fetches the keys of every listed member from GitHub; see
+/// [`KeyServer::fetch_group_key_set`].
+pub async fn fetch_group_key_set(list_usernames: Vec<String>) -> Result<GroupKeySet>{
+    KeyServer::default().fetch_group_key_set(list_usernames).await
+}
+
+/// Splits the SHA-512 of the canonical encoding of `message` into the five
+/// 120-bit limbs of the `message` circuit input.
+pub fn message_hash_limbs(message: &CanonicalMessage) -> Result<Vec<u128>>{
+    convert_byte_to_chunks(120, 5, &message.hash())
+}
+
+/// Builds `PublicSignals` for an already assembled key set.
+pub fn create_pb_signals_from_key_set(key_set: &GroupKeySet, message: &CanonicalMessage) -> Result<PublicSignals>{
+    let message_hash = message_hash_limbs(message)?;
+    Ok(PublicSignals{
+        message_hash,
+        keys: key_set.padded_limbs(MAX_GROUP_SIZE)?,
+    })
+}
+
+/// High-level helper that, given a list of GitHub usernames and the signed
+/// `message`, constructs a fully-populated `PublicSignals` instance ready for
+/// proof generation.
+pub async fn create_pb_signals_struct(list_usernames: Vec<String>, message: &CanonicalMessage) -> Result<PublicSignals>{
+    let key_set = fetch_group_key_set(list_usernames).await?;
+    create_pb_signals_from_key_set(&key_set, message)
 }
 
 /// Flattens the nested `PublicSignals` structure into a single `Vec<String>` so
 /// that it can be passed directly to snarkJS or a Circom verifier.
+#[allow(non_snake_case)]
 pub async fn convert_publicSignals(pb_signals: PublicSignals) -> Vec<String>{
     let mut result : Vec<String> = Vec::new();
     for block in pb_signals.message_hash{
@@ -270,8 +470,6 @@ pub async fn convert_publicSignals(pb_signals: PublicSignals) -> Vec<String>{
 
 /// Convenience wrapper that combines `create_pb_signals_struct` and
 /// `convert_publicSignals` in one call.
-pub async fn create_pb_signals(list_usernames: Vec<String>, message: &str) -> anyhow::Result<Vec<String>>{
+pub async fn create_pb_signals(list_usernames: Vec<String>, message: &CanonicalMessage) -> Result<Vec<String>>{
     Ok(convert_publicSignals(create_pb_signals_struct(list_usernames, message).await?).await)
 }
-
-
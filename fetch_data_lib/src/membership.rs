@@ -0,0 +1,138 @@
+//! Policy for senders that are listed in a submission but cannot have signed
+//! it because they publish no usable key of the submission's family.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{FetchError, GroupKeySet, KeyType, Result};
+
+/// What to do with listed senders that contribute no key to the group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MembershipPolicy {
+    /// Refuse the whole submission.
+    Reject,
+    /// Accept the submission but leave those senders out of the displayed
+    /// list, reporting them as a warning.
+    #[default]
+    Drop,
+}
+
+impl FromStr for MembershipPolicy {
+    type Err = FetchError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "reject" | "strict" => Ok(MembershipPolicy::Reject),
+            "drop" => Ok(MembershipPolicy::Drop),
+            other => Err(FetchError::invalid_input(format!(
+                "Unknown membership policy '{}': expected 'reject' or 'drop'",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for MembershipPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MembershipPolicy::Reject => write!(f, "reject"),
+            MembershipPolicy::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+/// Result of applying a [`MembershipPolicy`] to the listed senders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MembershipOutcome {
+    /// Senders that contribute at least one key, in the order they were listed.
+    pub eligible: Vec<String>,
+    /// Senders without a usable key, in the order they were listed.
+    pub ineligible: Vec<String>,
+    /// Family of the key set the senders were checked against.
+    pub key_type: KeyType,
+}
+
+impl MembershipOutcome {
+    /// Human-readable warning naming the ineligible senders, if there are any.
+    pub fn warning(&self) -> Option<String> {
+        if self.ineligible.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "The following senders have no usable {} key and were not listed: {}",
+            self.key_type,
+            self.ineligible.join(", ")
+        ))
+    }
+}
+
+/// Splits `senders` into eligible and ineligible members of `key_set` and
+/// applies `policy`. With [`MembershipPolicy::Reject`] any ineligible sender
+/// turns into an error that names all of them.
+pub fn apply_membership_policy(
+    senders: &[String],
+    key_set: &GroupKeySet,
+    policy: MembershipPolicy,
+) -> Result<MembershipOutcome> {
+    let contributions = key_set.contributions();
+    let mut eligible: Vec<String> = Vec::new();
+    let mut ineligible: Vec<String> = Vec::new();
+    for sender in senders {
+        if eligible.contains(sender) || ineligible.contains(sender) {
+            continue;
+        }
+        if contributions.contains_key(sender) {
+            eligible.push(sender.clone());
+        } else {
+            ineligible.push(sender.clone());
+        }
+    }
+    if policy == MembershipPolicy::Reject && !ineligible.is_empty() {
+        return Err(FetchError::IneligibleSenders { senders: ineligible, key_type: key_set.key_type() });
+    }
+    Ok(MembershipOutcome { eligible, ineligible, key_type: key_set.key_type() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GroupKeySetBuilder;
+
+    fn senders(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn key_set() -> GroupKeySet {
+        let mut builder = GroupKeySetBuilder::new();
+        builder.add_key("alice", &[3]).add_member("bob").add_key("carol", &[5]);
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn drop_keeps_order_and_reports_ineligible() {
+        let outcome = apply_membership_policy(
+            &senders(&["carol", "bob", "alice", "carol"]),
+            &key_set(),
+            MembershipPolicy::Drop,
+        )
+        .unwrap();
+        assert_eq!(outcome.eligible, senders(&["carol", "alice"]));
+        assert_eq!(outcome.ineligible, senders(&["bob"]));
+        assert!(outcome.warning().unwrap().contains("bob"));
+    }
+
+    #[test]
+    fn reject_names_ineligible_senders() {
+        let err = apply_membership_policy(&senders(&["alice", "bob"]), &key_set(), MembershipPolicy::Reject)
+            .unwrap_err();
+        assert!(err.to_string().contains("bob"));
+        assert!(apply_membership_policy(&senders(&["alice"]), &key_set(), MembershipPolicy::Reject).is_ok());
+    }
+
+    #[test]
+    fn parses_policy_names() {
+        assert_eq!("Reject".parse::<MembershipPolicy>().unwrap(), MembershipPolicy::Reject);
+        assert_eq!("drop".parse::<MembershipPolicy>().unwrap(), MembershipPolicy::Drop);
+        assert!("maybe".parse::<MembershipPolicy>().is_err());
+    }
+}
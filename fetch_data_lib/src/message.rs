@@ -0,0 +1,105 @@
+//! Canonical byte encoding of an email, shared by the server and by clients
+//! that produce proofs.
+//!
+//! The proof commits to the SHA-512 of this encoding rather than to the raw
+//! body, so the recipient, the subject and the optional timestamp cannot be
+//! changed without invalidating the signature. Every field is length-prefixed
+//! (u32, big-endian) and optional fields carry a presence byte, which keeps the
+//! encoding unambiguous. The leading tag separates it from any other data a
+//! member might sign with the same key.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+/// Domain-separation tag placed in front of every encoded message.
+pub const MESSAGE_DOMAIN_TAG: &[u8] = b"send_group_emails/message/v1";
+
+/// The parts of an email that a group signature covers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanonicalMessage {
+    /// Recipient address as submitted. `None` means the server's default list.
+    pub recipient: Option<String>,
+    pub header: String,
+    pub body: String,
+    /// Optional UNIX timestamp (seconds) chosen by the signer.
+    pub timestamp: Option<u64>,
+}
+
+impl CanonicalMessage {
+    pub fn new(recipient: Option<String>, header: &str, body: &str, timestamp: Option<u64>) -> Self {
+        Self {
+            recipient,
+            header: header.to_string(),
+            body: body.to_string(),
+            timestamp,
+        }
+    }
+
+    /// The exact bytes that are hashed and signed.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::new();
+        push_field(&mut result, MESSAGE_DOMAIN_TAG);
+        match &self.recipient {
+            Some(recipient) => {
+                result.push(1);
+                push_field(&mut result, recipient.as_bytes());
+            }
+            None => result.push(0),
+        }
+        push_field(&mut result, self.header.as_bytes());
+        push_field(&mut result, self.body.as_bytes());
+        match self.timestamp {
+            Some(timestamp) => {
+                result.push(1);
+                result.extend_from_slice(&timestamp.to_be_bytes());
+            }
+            None => result.push(0),
+        }
+        result
+    }
+
+    /// SHA-512 of [`CanonicalMessage::encode`].
+    pub fn hash(&self) -> [u8; 64] {
+        Sha512::digest(self.encode()).into()
+    }
+}
+
+fn push_field(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    out.extend_from_slice(field);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_fields_with_length_prefixes() {
+        let message = CanonicalMessage::new(None, "Hi", "Kudos", Some(1));
+        let mut expected: Vec<u8> = Vec::new();
+        expected.extend_from_slice(&(MESSAGE_DOMAIN_TAG.len() as u32).to_be_bytes());
+        expected.extend_from_slice(MESSAGE_DOMAIN_TAG);
+        expected.push(0);
+        expected.extend_from_slice(&[0, 0, 0, 2]);
+        expected.extend_from_slice(b"Hi");
+        expected.extend_from_slice(&[0, 0, 0, 5]);
+        expected.extend_from_slice(b"Kudos");
+        expected.push(1);
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(message.encode(), expected);
+    }
+
+    #[test]
+    fn every_field_changes_the_hash() {
+        let base = CanonicalMessage::new(Some("a@b.c".to_string()), "ab", "c", None);
+        let variants = [
+            CanonicalMessage::new(None, "ab", "c", None),
+            CanonicalMessage::new(Some("x@b.c".to_string()), "ab", "c", None),
+            CanonicalMessage::new(Some("a@b.c".to_string()), "a", "bc", None),
+            CanonicalMessage::new(Some("a@b.c".to_string()), "ab", "c", Some(0)),
+        ];
+        for variant in variants {
+            assert_ne!(base.hash(), variant.hash(), "{variant:?}");
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//! Minimal OpenPGP (RFC 4880) public-key reader for the armored keyrings
+//! GitHub publishes at `https://github.com/<user>.gpg`.
+//!
+//! Only what is needed to recover RSA moduli is implemented: ASCII armor with
+//! its CRC-24 checksum, packet framing, v4 public-key and public-subkey
+//! packets, and the key-flags, key-expiration and creation-time subpackets of
+//! self-signatures. Primary RSA keys are returned unless revoked or expired;
+//! RSA subkeys only when they may sign and neither they nor their primary key
+//! are revoked or expired.
+//!
+//! Signatures are not verified. Treating an unverified revocation as real
+//! can only drop a key, and the newest self-signature decides expiry and key
+//! flags, as it does in GnuPG.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use sha1::{Digest, Sha1};
+
+use crate::error::malformed;
+use crate::{FetchError, Result};
+
+const TAG_SIGNATURE: u8 = 2;
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+
+const ALGORITHM_RSA: u8 = 1;
+const ALGORITHM_RSA_SIGN_ONLY: u8 = 3;
+
+const SIGNATURE_CERTIFICATION_FIRST: u8 = 0x10;
+const SIGNATURE_CERTIFICATION_LAST: u8 = 0x13;
+const SIGNATURE_SUBKEY_BINDING: u8 = 0x18;
+const SIGNATURE_DIRECT_KEY: u8 = 0x1f;
+const SIGNATURE_KEY_REVOCATION: u8 = 0x20;
+const SIGNATURE_SUBKEY_REVOCATION: u8 = 0x28;
+
+const SUBPACKET_CREATION_TIME: u8 = 2;
+const SUBPACKET_KEY_EXPIRATION: u8 = 9;
+const SUBPACKET_KEY_FLAGS: u8 = 27;
+const KEY_FLAG_SIGN: u8 = 0x02;
+
+/// An RSA key found in an OpenPGP keyring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenPgpRsaKey {
+    /// Upper-case hex v4 fingerprint, as printed by `gpg --fingerprint`.
+    pub fingerprint: String,
+    pub is_subkey: bool,
+    /// Big-endian modulus and exponent without leading zeros.
+    pub modulus: Vec<u8>,
+    pub exponent: Vec<u8>,
+}
+
+/// Removes the ASCII armor and returns the binary packets. The CRC-24
+/// checksum is verified when present.
+pub fn dearmor(armored: &str) -> Result<Vec<u8>> {
+    let mut lines = armored.lines().map(str::trim);
+    lines
+        .by_ref()
+        .find(|line| line.starts_with("-----BEGIN PGP PUBLIC KEY BLOCK-----"))
+        .ok_or_else(|| malformed!("Invalid OpenPGP armor: missing BEGIN PGP PUBLIC KEY BLOCK"))?;
+    // armor headers (e.g. "Comment: ...") end at the first empty line
+    for line in lines.by_ref() {
+        if line.is_empty() {
+            break;
+        }
+    }
+
+    let mut body = String::new();
+    let mut checksum: Option<&str> = None;
+    let mut terminated = false;
+    for line in lines {
+        if line.starts_with("-----END PGP PUBLIC KEY BLOCK-----") {
+            terminated = true;
+            break;
+        }
+        match line.strip_prefix('=') {
+            Some(crc) => checksum = Some(crc),
+            None => body.push_str(line),
+        }
+    }
+    if !terminated {
+        return Err(malformed!("Invalid OpenPGP armor: missing END PGP PUBLIC KEY BLOCK"));
+    }
+
+    let data = STANDARD
+        .decode(body)
+        .map_err(|err| malformed!("Invalid OpenPGP armor: {}", err))?;
+    if let Some(checksum) = checksum {
+        let expected = STANDARD
+            .decode(checksum)
+            .map_err(|err| malformed!("Invalid OpenPGP armor checksum: {}", err))?;
+        if expected != crc24(&data).to_be_bytes()[1..] {
+            return Err(malformed!("Invalid OpenPGP armor: checksum mismatch"));
+        }
+    }
+    Ok(data)
+}
+
+/// Parses an armored keyring and returns the usable RSA primary keys and the
+/// usable RSA subkeys that are allowed to sign, judging expiry by the clock.
+pub fn parse_openpgp_rsa_keys(armored: &str) -> Result<Vec<OpenPgpRsaKey>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    parse_openpgp_rsa_keys_at(armored, now)
+}
+
+/// [`parse_openpgp_rsa_keys`], with keys that expire at or before `now`
+/// (UNIX seconds) left out.
+pub fn parse_openpgp_rsa_keys_at(armored: &str, now: u64) -> Result<Vec<OpenPgpRsaKey>> {
+    let data = dearmor(armored)?;
+    let packets = split_packets(&data)?;
+
+    // each primary key with the subkeys that follow it
+    let mut certificates: Vec<(KeyState, Vec<KeyState>)> = Vec::new();
+    for (tag, body) in packets {
+        match tag {
+            TAG_PUBLIC_KEY => certificates.push((KeyState::read(body, false)?, Vec::new())),
+            TAG_PUBLIC_SUBKEY => {
+                let subkey = KeyState::read(body, true)?;
+                if let Some((_, subkeys)) = certificates.last_mut() {
+                    subkeys.push(subkey);
+                }
+            }
+            TAG_SIGNATURE => {
+                let Some(signature) = Signature::read(body)? else {
+                    continue;
+                };
+                // a signature belongs to the key packet it follows
+                match certificates.last_mut() {
+                    Some((primary, subkeys)) => match subkeys.last_mut() {
+                        Some(subkey) => subkey.apply(&signature),
+                        None => primary.apply(&signature),
+                    },
+                    None => continue,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut result: Vec<OpenPgpRsaKey> = Vec::new();
+    for (primary, subkeys) in certificates {
+        if !primary.is_live(now) {
+            continue;
+        }
+        result.extend(primary.key);
+        for subkey in subkeys {
+            // an unbound subkey is only usable if its algorithm says so
+            let may_sign = subkey.flags.is_some_and(|flags| flags & KEY_FLAG_SIGN != 0)
+                || subkey.algorithm == ALGORITHM_RSA_SIGN_ONLY;
+            if may_sign && subkey.is_live(now) {
+                result.extend(subkey.key);
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// What the signatures following a key packet say about the key.
+struct KeyState {
+    /// `None` for keys that are not RSA; they still carry their subkeys.
+    key: Option<OpenPgpRsaKey>,
+    algorithm: u8,
+    is_subkey: bool,
+    created: u64,
+    revoked: bool,
+    /// From the newest self-signature, the one dated `signed_at`.
+    expires_after: Option<u64>,
+    flags: Option<u8>,
+    signed_at: Option<u64>,
+}
+
+impl KeyState {
+    fn read(body: &[u8], is_subkey: bool) -> Result<Self> {
+        let created = match body.first() {
+            Some(4) => u32::from_be_bytes(array(body.get(1..5).ok_or_else(truncated)?)?) as u64,
+            _ => 0,
+        };
+        let (key, algorithm) = match parse_public_key_packet(body, is_subkey)? {
+            Some((key, algorithm)) => (Some(key), algorithm),
+            None => (None, *body.get(5).unwrap_or(&0)),
+        };
+        Ok(Self { key, algorithm, is_subkey, created, revoked: false, expires_after: None, flags: None, signed_at: None })
+    }
+
+    fn apply(&mut self, signature: &Signature) {
+        let is_subkey = self.is_subkey;
+        match signature.kind {
+            SIGNATURE_KEY_REVOCATION if !is_subkey => self.revoked = true,
+            SIGNATURE_SUBKEY_REVOCATION if is_subkey => self.revoked = true,
+            SIGNATURE_SUBKEY_BINDING if is_subkey => self.self_signed(signature),
+            SIGNATURE_DIRECT_KEY | SIGNATURE_CERTIFICATION_FIRST..=SIGNATURE_CERTIFICATION_LAST if !is_subkey => {
+                self.self_signed(signature)
+            }
+            _ => {}
+        }
+    }
+
+    fn self_signed(&mut self, signature: &Signature) {
+        if self.signed_at.is_some_and(|signed_at| signature.created < signed_at) {
+            return;
+        }
+        self.signed_at = Some(signature.created);
+        self.expires_after = signature.key_expires_after;
+        self.flags = Some(signature.key_flags.unwrap_or(0));
+    }
+
+    /// Not revoked, and not expired at `now`. An expiry of 0 means never.
+    fn is_live(&self, now: u64) -> bool {
+        let expired = self
+            .expires_after
+            .is_some_and(|after| after > 0 && self.created + after <= now);
+        !self.revoked && !expired
+    }
+}
+
+/// The parts of a signature packet this reader looks at. Only the hashed
+/// subpackets of v4 signatures are read.
+struct Signature {
+    kind: u8,
+    created: u64,
+    key_expires_after: Option<u64>,
+    key_flags: Option<u8>,
+}
+
+impl Signature {
+    /// Returns `None` for signature versions this reader does not handle.
+    fn read(body: &[u8]) -> Result<Option<Self>> {
+        match body.first() {
+            // version, length of the hashed part (5), type, creation time, ...
+            Some(3) => Ok(Some(Signature {
+                kind: *body.get(2).ok_or_else(truncated)?,
+                created: u32::from_be_bytes(array(body.get(3..7).ok_or_else(truncated)?)?) as u64,
+                key_expires_after: None,
+                key_flags: None,
+            })),
+            Some(4) => {
+                let kind = *body.get(1).ok_or_else(truncated)?;
+                let len = u16::from_be_bytes(array(body.get(4..6).ok_or_else(truncated)?)?) as usize;
+                let mut signature = Signature { kind, created: 0, key_expires_after: None, key_flags: None };
+                let mut subpackets = body.get(6..6 + len).ok_or_else(truncated)?;
+                while !subpackets.is_empty() {
+                    let first = subpackets[0] as usize;
+                    let (len, header) = match first {
+                        0..=191 => (first, 1),
+                        192..=254 => (((first - 192) << 8) + *subpackets.get(1).ok_or_else(truncated)? as usize + 192, 2),
+                        _ => (u32::from_be_bytes(array(subpackets.get(1..5).ok_or_else(truncated)?)?) as usize, 5),
+                    };
+                    let subpacket = subpackets.get(header..header + len).ok_or_else(truncated)?;
+                    let value = subpacket.get(1..).unwrap_or_default();
+                    match subpacket.first().map(|kind| kind & 0x7f) {
+                        Some(SUBPACKET_CREATION_TIME) => signature.created = u32::from_be_bytes(array(value)?) as u64,
+                        Some(SUBPACKET_KEY_EXPIRATION) => {
+                            signature.key_expires_after = Some(u32::from_be_bytes(array(value)?) as u64)
+                        }
+                        Some(SUBPACKET_KEY_FLAGS) => signature.key_flags = Some(*value.first().unwrap_or(&0)),
+                        _ => {}
+                    }
+                    subpackets = &subpackets[header + len..];
+                }
+                Ok(Some(signature))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Splits binary OpenPGP data into `(tag, body)` pairs.
+fn split_packets(data: &[u8]) -> Result<Vec<(u8, &[u8])>> {
+    let mut result: Vec<(u8, &[u8])> = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let header = data[offset];
+        if header & 0x80 == 0 {
+            return Err(malformed!("Invalid OpenPGP packet header at offset {}", offset));
+        }
+        offset += 1;
+        let (tag, len) = if header & 0x40 != 0 {
+            // new format
+            let tag = header & 0x3f;
+            let first = *data.get(offset).ok_or_else(truncated)? as usize;
+            offset += 1;
+            let len = match first {
+                0..=191 => first,
+                192..=223 => {
+                    let second = *data.get(offset).ok_or_else(truncated)? as usize;
+                    offset += 1;
+                    ((first - 192) << 8) + second + 192
+                }
+                255 => {
+                    let bytes = data.get(offset..offset + 4).ok_or_else(truncated)?;
+                    offset += 4;
+                    u32::from_be_bytes(array(bytes)?) as usize
+                }
+                _ => return Err(malformed!("Partial-length OpenPGP packets are not supported in keys")),
+            };
+            (tag, len)
+        } else {
+            // old format
+            let tag = (header >> 2) & 0x0f;
+            let len = match header & 0x03 {
+                0 => {
+                    let len = *data.get(offset).ok_or_else(truncated)? as usize;
+                    offset += 1;
+                    len
+                }
+                1 => {
+                    let bytes = data.get(offset..offset + 2).ok_or_else(truncated)?;
+                    offset += 2;
+                    u16::from_be_bytes(array(bytes)?) as usize
+                }
+                2 => {
+                    let bytes = data.get(offset..offset + 4).ok_or_else(truncated)?;
+                    offset += 4;
+                    u32::from_be_bytes(array(bytes)?) as usize
+                }
+                _ => data.len() - offset,
+            };
+            (tag, len)
+        };
+        let body = data.get(offset..offset + len).ok_or_else(truncated)?;
+        offset += len;
+        result.push((tag, body));
+    }
+    Ok(result)
+}
+
+/// Reads a v4 public-key packet. Returns `None` for non-RSA keys and for
+/// packet versions this reader does not handle.
+fn parse_public_key_packet(body: &[u8], is_subkey: bool) -> Result<Option<(OpenPgpRsaKey, u8)>> {
+    if body.first() != Some(&4) {
+        return Ok(None);
+    }
+    let algorithm = *body.get(5).ok_or_else(truncated)?;
+    if algorithm != ALGORITHM_RSA && algorithm != ALGORITHM_RSA_SIGN_ONLY {
+        return Ok(None);
+    }
+    let mut offset = 6;
+    let modulus = read_mpi(body, &mut offset)?;
+    let exponent = read_mpi(body, &mut offset)?;
+
+    let mut hasher = Sha1::new();
+    hasher.update([0x99]);
+    hasher.update((body.len() as u16).to_be_bytes());
+    hasher.update(body);
+    let fingerprint = hasher.finalize().iter().map(|byte| format!("{:02X}", byte)).collect();
+
+    Ok(Some((OpenPgpRsaKey { fingerprint, is_subkey, modulus, exponent }, algorithm)))
+}
+
+fn read_mpi(body: &[u8], offset: &mut usize) -> Result<Vec<u8>> {
+    let bits = u16::from_be_bytes(array(body.get(*offset..*offset + 2).ok_or_else(truncated)?)?) as usize;
+    *offset += 2;
+    let len = bits.div_ceil(8);
+    let value = body.get(*offset..*offset + len).ok_or_else(truncated)?;
+    *offset += len;
+    let start = value.iter().position(|byte| *byte != 0).unwrap_or(value.len());
+    if start == value.len() {
+        return Err(malformed!("Invalid OpenPGP key: zero MPI"));
+    }
+    Ok(value[start..].to_vec())
+}
+
+fn truncated() -> FetchError {
+    malformed!("Truncated OpenPGP packet")
+}
+
+fn array<const N: usize>(bytes: &[u8]) -> Result<[u8; N]> {
+    bytes.try_into().map_err(|_| truncated())
+}
+
+/// CRC-24 as defined in RFC 4880, section 6.1.
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xB704CE;
+    for byte in data {
+        crc ^= (*byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= 0x1864CFB;
+            }
+        }
+    }
+    crc & 0xFFFFFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keyring made with GnuPG: RSA primary (sign/certify), RSA encryption
+    // subkey, RSA signing subkey and an ed25519 signing subkey.
+    const KEYRING: &str = include_str!("../fixtures/alice.gpg");
+
+    #[test]
+    fn extracts_primary_and_signing_subkeys() {
+        let keys = parse_openpgp_rsa_keys(KEYRING).unwrap();
+        assert_eq!(
+            fingerprints(&keys),
+            vec!["A9119CCB0B428E0D9F3C0F5AF891B7C79C63561A", "69ED21600A9F0125BBDB2760A9C1673D434A406D"]
+        );
+        assert!(!keys[0].is_subkey && keys[1].is_subkey);
+        assert!(keys.iter().all(|key| key.modulus.len() == 256 && key.exponent == vec![1, 0, 1]));
+    }
+
+    fn fingerprints(keys: &[OpenPgpRsaKey]) -> Vec<&str> {
+        keys.iter().map(|key| key.fingerprint.as_str()).collect()
+    }
+
+    // dan's primary key is revoked, and with it his signing subkey; the
+    // first of erin's two signing subkeys is revoked.
+    #[test]
+    fn skips_revoked_keys() {
+        let keys = parse_openpgp_rsa_keys(include_str!("../fixtures/revoked.gpg")).unwrap();
+        assert_eq!(
+            fingerprints(&keys),
+            vec!["16D0B7510CFCA94EC95284C48EFB77BA7F91D418", "0DA126464A17514F3009F6B88D29A8AE0E6F8B86"]
+        );
+    }
+
+    // Made on 2020-01-01: frank's primary key expires after a year, the
+    // first of gina's two signing subkeys after a day.
+    #[test]
+    fn skips_expired_keys() {
+        const EXPIRED: &str = include_str!("../fixtures/expired.gpg");
+        const CREATED: u64 = 1_577_836_800;
+        assert_eq!(
+            fingerprints(&parse_openpgp_rsa_keys(EXPIRED).unwrap()),
+            vec!["2DAB74E3EA20A0E6FAE228E7DFF97602C02A6C2B", "65B6CE0CEE39BD94946141FB9D76C9D20D4BD90F"]
+        );
+        assert_eq!(
+            fingerprints(&parse_openpgp_rsa_keys_at(EXPIRED, CREATED + 3600).unwrap()),
+            vec![
+                "573F14F33DE7E3CDAC4085406EBE1474592464F5",
+                "2DAB74E3EA20A0E6FAE228E7DFF97602C02A6C2B",
+                "FCB895E0EA30E6CA7EEB6C39B62726586404A265",
+                "65B6CE0CEE39BD94946141FB9D76C9D20D4BD90F",
+            ]
+        );
+        assert_eq!(parse_openpgp_rsa_keys_at(EXPIRED, CREATED + 86_400).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn accepts_github_placeholder_for_users_without_keys() {
+        let armored = "-----BEGIN PGP PUBLIC KEY BLOCK-----\nNote: This user hasn't uploaded any GPG keys.\n\n\n=twTO\n-----END PGP PUBLIC KEY BLOCK-----\n";
+        assert!(parse_openpgp_rsa_keys(armored).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_corrupted_armor() {
+        let corrupted = KEYRING.replacen("mQENB", "mQENC", 1);
+        assert!(parse_openpgp_rsa_keys(&corrupted).unwrap_err().to_string().contains("checksum"));
+        assert!(parse_openpgp_rsa_keys("ssh-rsa AAAA").is_err());
+    }
+}
@@ -0,0 +1,454 @@
+//! Line-oriented parser for OpenSSH public keys, as found in `.pub` files,
+//! `authorized_keys` and GitHub's `<user>.keys` endpoint.
+//!
+//! Each non-empty, non-comment line is `[options] keytype base64 [comment]`.
+//! The key type written in the line must match the one embedded in the
+//! decoded blob. Lines that cannot be understood are returned as
+//! [`UnsupportedLine`]s instead of being silently skipped.
+
+use std::fmt;
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine as _;
+use sha2::{Digest, Sha256};
+
+use crate::error::malformed;
+use crate::{check_rsa_exponent, FetchError, KeyType, Result, RSA_EXPONENT};
+
+/// Key types (and their certificate variants) that OpenSSH understands.
+const KNOWN_KEY_TYPES: &[&str] = &[
+    "ssh-rsa",
+    "ssh-dss",
+    "ssh-ed25519",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+    "ssh-rsa-cert-v01@openssh.com",
+    "ssh-dss-cert-v01@openssh.com",
+    "ssh-ed25519-cert-v01@openssh.com",
+    "ecdsa-sha2-nistp256-cert-v01@openssh.com",
+    "ecdsa-sha2-nistp384-cert-v01@openssh.com",
+    "ecdsa-sha2-nistp521-cert-v01@openssh.com",
+    "sk-ssh-ed25519-cert-v01@openssh.com",
+    "sk-ecdsa-sha2-nistp256-cert-v01@openssh.com",
+];
+
+const CERT_SUFFIX: &str = "-cert-v01@openssh.com";
+
+/// A parsed OpenSSH public key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    /// Key type, e.g. `ssh-rsa` or `ssh-ed25519-cert-v01@openssh.com`.
+    pub key_type: String,
+    /// Decoded wire-format key blob.
+    pub blob: Vec<u8>,
+    pub comment: Option<String>,
+    /// Raw `authorized_keys` options, e.g. `no-pty,command="ls"`.
+    pub options: Option<String>,
+}
+
+impl PublicKey {
+    /// Builds a key from a wire-format blob, taking the type from the blob.
+    pub fn from_blob(blob: &[u8]) -> Result<Self> {
+        let key_type = String::from_utf8(WireReader::new(blob).string()?.to_vec())
+            .map_err(|_| malformed!("Key type is not valid UTF-8"))?;
+        Ok(Self { key_type, blob: blob.to_vec(), comment: None, options: None })
+    }
+
+    pub fn is_certificate(&self) -> bool {
+        self.key_type.ends_with(CERT_SUFFIX)
+    }
+
+    /// Key type with any certificate suffix removed.
+    pub fn base_key_type(&self) -> &str {
+        self.key_type.strip_suffix(CERT_SUFFIX).unwrap_or(&self.key_type)
+    }
+
+    /// `SHA256:<base64>` fingerprint, identical to `ssh-keygen -lf`.
+    pub fn fingerprint(&self) -> String {
+        format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(&self.blob)))
+    }
+
+    /// RSA modulus `n` and exponent `e` as big-endian bytes without leading
+    /// zeros. Works for plain `ssh-rsa` keys and RSA certificates.
+    pub fn rsa_components(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        if self.base_key_type() != "ssh-rsa" {
+            return Err(malformed!("Unsupported key type: {}", self.key_type));
+        }
+        let mut reader = WireReader::new(&self.blob);
+        reader.string()?;
+        if self.is_certificate() {
+            // nonce, then e and n as in a plain key
+            reader.string()?;
+        }
+        let e = reader.mpint()?;
+        let n = reader.mpint()?;
+        if e.is_empty() || n.is_empty() {
+            return Err(malformed!("Invalid length: zero length field"));
+        }
+        if !self.is_certificate() && !reader.is_empty() {
+            return Err(malformed!("Extra data after modulus"));
+        }
+        Ok((n, e))
+    }
+
+    /// Family and circuit-ready key material (see [`KeyType`]): the RSA
+    /// modulus, the 32-byte Ed25519 point or `X || Y` of a P-256 point.
+    /// Certificates yield the certified key. RSA keys with an exponent other
+    /// than [`RSA_EXPONENT`] are refused.
+    pub fn key_material(&self) -> Result<(KeyType, Vec<u8>)> {
+        let key_type = KeyType::from_ssh_key_type(self.base_key_type())
+            .ok_or_else(|| malformed!("Unsupported key type: {}", self.key_type))?;
+        if key_type == KeyType::Rsa {
+            let (modulus, exponent) = self.rsa_components()?;
+            check_rsa_exponent(&exponent)?;
+            return Ok((key_type, modulus));
+        }
+        let mut reader = WireReader::new(&self.blob);
+        reader.string()?;
+        if self.is_certificate() {
+            reader.string()?;
+        }
+        let material = match key_type {
+            KeyType::Ed25519 => reader.string()?.to_vec(),
+            _ => {
+                if reader.string()? != b"nistp256" {
+                    return Err(malformed!("Invalid ECDSA key: curve does not match {}", self.key_type));
+                }
+                match reader.string()?.split_first() {
+                    // SEC1 uncompressed point
+                    Some((0x04, point)) => point.to_vec(),
+                    _ => return Err(malformed!("Invalid ECDSA key: expected an uncompressed point")),
+                }
+            }
+        };
+        if !self.is_certificate() && !reader.is_empty() {
+            return Err(malformed!("Extra data after public key"));
+        }
+        if Some(material.len()) != key_type.key_len() {
+            return Err(malformed!("Invalid {} key: {} bytes of key data", key_type, material.len()));
+        }
+        Ok((key_type, material))
+    }
+
+    /// The plain OpenSSH key holding circuit key material, e.g. to compute
+    /// its fingerprint. RSA keys get the exponent [`RSA_EXPONENT`]: it is the
+    /// only one [`PublicKey::key_material`] lets through, so the fingerprint
+    /// is the one `ssh-keygen -l` prints for the published key.
+    pub fn from_key_material(key_type: KeyType, material: &[u8]) -> PublicKey {
+        let mut blob: Vec<u8> = Vec::new();
+        let ssh_key_type = match key_type {
+            KeyType::Rsa => {
+                blob = rsa_blob(&RSA_EXPONENT.to_be_bytes(), material);
+                "ssh-rsa"
+            }
+            KeyType::Ed25519 => {
+                put_string(&mut blob, b"ssh-ed25519");
+                put_string(&mut blob, material);
+                "ssh-ed25519"
+            }
+            KeyType::EcdsaP256 => {
+                put_string(&mut blob, b"ecdsa-sha2-nistp256");
+                put_string(&mut blob, b"nistp256");
+                put_string(&mut blob, &[&[0x04], material].concat());
+                "ecdsa-sha2-nistp256"
+            }
+        };
+        PublicKey { key_type: ssh_key_type.to_string(), blob, comment: None, options: None }
+    }
+
+    /// The key in `keytype base64` form, without options or comment.
+    pub fn to_openssh(&self) -> String {
+        format!("{} {}", self.key_type, STANDARD.encode(&self.blob))
+    }
+}
+
+/// A line that could not be parsed as a public key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedLine {
+    /// 1-based line number.
+    pub line_number: usize,
+    pub line: String,
+    pub reason: String,
+}
+
+impl fmt::Display for UnsupportedLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line_number, self.reason)
+    }
+}
+
+/// Everything found in a block of public-key text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedKeys {
+    pub keys: Vec<PublicKey>,
+    pub unsupported: Vec<UnsupportedLine>,
+}
+
+impl ParsedKeys {
+    /// Keys of one family, e.g. every `ssh-ed25519` key and certificate.
+    pub fn keys_of_type(&self, key_type: KeyType) -> impl Iterator<Item = &PublicKey> {
+        self.keys
+            .iter()
+            .filter(move |key| KeyType::from_ssh_key_type(key.base_key_type()) == Some(key_type))
+    }
+
+    /// Only the RSA keys (including RSA certificates).
+    pub fn rsa_keys(&self) -> impl Iterator<Item = &PublicKey> {
+        self.keys.iter().filter(|key| key.base_key_type() == "ssh-rsa")
+    }
+}
+
+/// Parses every line of `text`. Blank lines and `#` comments are ignored.
+pub fn parse_public_keys(text: &str) -> ParsedKeys {
+    let mut result = ParsedKeys::default();
+    for (index, line) in text.lines().enumerate() {
+        match parse_public_key_line(line) {
+            Ok(Some(key)) => result.keys.push(key),
+            Ok(None) => {}
+            Err(err) => result.unsupported.push(UnsupportedLine {
+                line_number: index + 1,
+                line: line.to_string(),
+                reason: match err {
+                    FetchError::MalformedKey { reason, .. } => reason,
+                    other => other.to_string(),
+                },
+            }),
+        }
+    }
+    result
+}
+
+/// Parses a single line. Returns `Ok(None)` for blank and comment lines.
+pub fn parse_public_key_line(line: &str) -> Result<Option<PublicKey>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let (first, rest) = split_token(line);
+    let (options, key_type, rest) = if is_known_key_type(first) {
+        (None, first, rest)
+    } else {
+        let (options, rest) = split_options(line)?;
+        let (key_type, rest) = split_token(rest);
+        if !is_known_key_type(key_type) {
+            return Err(malformed!("Unsupported key type: {}", first));
+        }
+        (Some(options.to_string()), key_type, rest)
+    };
+
+    let (encoded, comment) = split_token(rest);
+    if encoded.is_empty() {
+        return Err(malformed!("Invalid SSH key format: missing key data"));
+    }
+    let blob = STANDARD
+        .decode(encoded)
+        .map_err(|err| malformed!("Invalid base64 key data: {}", err))?;
+    let embedded = PublicKey::from_blob(&blob)?;
+    if embedded.key_type != key_type {
+        return Err(malformed!(
+            "Key type mismatch: line says {} but key data is {}",
+            key_type,
+            embedded.key_type
+        ));
+    }
+
+    Ok(Some(PublicKey {
+        key_type: key_type.to_string(),
+        blob,
+        comment: (!comment.is_empty()).then(|| comment.to_string()),
+        options,
+    }))
+}
+
+fn is_known_key_type(token: &str) -> bool {
+    KNOWN_KEY_TYPES.contains(&token)
+}
+
+/// Splits off the first whitespace-separated token.
+fn split_token(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.find(char::is_whitespace) {
+        Some(end) => (&text[..end], text[end..].trim()),
+        None => (text, ""),
+    }
+}
+
+/// Splits off an `authorized_keys` options field, which ends at the first
+/// whitespace outside double quotes.
+fn split_options(text: &str) -> Result<(&str, &str)> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => return Ok((&text[..index], &text[index..])),
+            _ => {}
+        }
+    }
+    Err(malformed!("Invalid SSH key format: should include key type"))
+}
+
+/// Blob of an `ssh-rsa` key from big-endian exponent and modulus.
+pub(crate) fn rsa_blob(exponent: &[u8], modulus: &[u8]) -> Vec<u8> {
+    let mut blob: Vec<u8> = Vec::new();
+    put_string(&mut blob, b"ssh-rsa");
+    put_string(&mut blob, &encode_mpint(exponent));
+    put_string(&mut blob, &encode_mpint(modulus));
+    blob
+}
+
+pub(crate) fn put_string(out: &mut Vec<u8>, value: &[u8]) {
+    out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+/// SSH `mpint` encoding of a non-negative big-endian integer.
+pub(crate) fn encode_mpint(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len());
+    let mut result: Vec<u8> = Vec::with_capacity(bytes.len() - start + 1);
+    if bytes.get(start).is_some_and(|byte| byte & 0x80 != 0) {
+        result.push(0);
+    }
+    result.extend_from_slice(&bytes[start..]);
+    result
+}
+
+/// Reader for SSH wire-format data (RFC 4251 `uint32`, `string`, `mpint`).
+pub(crate) struct WireReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> WireReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + 4)
+            .ok_or_else(|| malformed!("Unexpected end of data when reading length"))?;
+        self.offset += 4;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        let value = self
+            .data
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| malformed!("Invalid length {}: exceeds remaining data", len))?;
+        self.offset += len;
+        Ok(value)
+    }
+
+    /// An `mpint` as unsigned big-endian bytes without leading zeros.
+    pub(crate) fn mpint(&mut self) -> Result<Vec<u8>> {
+        let value = self.string()?;
+        let start = value.iter().position(|byte| *byte != 0).unwrap_or(value.len());
+        Ok(value[start..].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSA: &str = include_str!("../fixtures/alice.pub");
+    const RSA_CERT: &str = include_str!("../fixtures/alice-cert.pub");
+    const ED25519: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHUAqyCmL8wF6Vsxr4O/ijvG4ujMKdeAeqGgCNY7AG4y";
+    const P256: &str = "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBHwHowXVMhnSJmvRdNLfAK6y+GiTokcXzf5+04o9MEoHi12CqnNvc/kyypbzq+Dgx6Jt+/FVpbA4hm1D6Dc1cmQ=";
+
+    #[test]
+    fn parses_comments_options_and_other_key_types() {
+        let text = format!(
+            "# team keys\n\n{} alice@ssh-laptop\nno-pty,command=\"echo ssh-rsa hi\" {ED25519} bob\n",
+            RSA.trim_end().trim_end_matches("alice@laptop")
+        );
+        let parsed = parse_public_keys(&text);
+        assert!(parsed.unsupported.is_empty(), "{:?}", parsed.unsupported);
+        assert_eq!(parsed.keys.len(), 2);
+        assert_eq!(parsed.keys[0].comment.as_deref(), Some("alice@ssh-laptop"));
+        assert_eq!(parsed.keys[1].key_type, "ssh-ed25519");
+        assert_eq!(parsed.keys[1].options.as_deref(), Some("no-pty,command=\"echo ssh-rsa hi\""));
+        assert_eq!(parsed.rsa_keys().count(), 1);
+    }
+
+    #[test]
+    fn key_material_keeps_the_fingerprint() {
+        for line in [RSA, ED25519, P256] {
+            let key = parse_public_key_line(line).unwrap().unwrap();
+            let (key_type, material) = key.key_material().unwrap();
+            assert_eq!(PublicKey::from_key_material(key_type, &material).fingerprint(), key.fingerprint());
+        }
+    }
+
+    #[test]
+    fn fingerprint_matches_ssh_keygen() {
+        let key = parse_public_key_line(ED25519).unwrap().unwrap();
+        // ssh-keygen -lf of the same key
+        assert_eq!(key.fingerprint(), "SHA256:LDJIhoZ25ZJzIcWsRHNQOYQYrq+ERLqrlbirZjUCTBY");
+    }
+
+    #[test]
+    fn reports_unsupported_lines() {
+        let text = format!("{}ssh-foo AAAA\nssh-rsa not-base64!\nssh-rsa {}\n", RSA, ED25519.split(' ').nth(1).unwrap());
+        let parsed = parse_public_keys(&text);
+        assert_eq!(parsed.keys.len(), 1);
+        let lines: Vec<usize> = parsed.unsupported.iter().map(|line| line.line_number).collect();
+        assert_eq!(lines, vec![2, 3, 4]);
+        assert!(parsed.unsupported[2].reason.contains("mismatch"));
+    }
+
+    #[test]
+    fn extracts_rsa_components() {
+        let key = parse_public_key_line(RSA).unwrap().unwrap();
+        let (n, e) = key.rsa_components().unwrap();
+        assert_eq!(e, vec![1, 0, 1]);
+        assert_eq!(n.len(), 256);
+        assert_ne!(n[0], 0);
+        assert!(parse_public_key_line(ED25519).unwrap().unwrap().rsa_components().is_err());
+    }
+
+    #[test]
+    fn extracts_key_material_of_every_family() {
+        let (key_type, material) = parse_public_key_line(ED25519).unwrap().unwrap().key_material().unwrap();
+        assert_eq!(key_type, KeyType::Ed25519);
+        assert_eq!(material.len(), 32);
+        assert_eq!(material[..2], [0x75, 0x00]);
+
+        let (key_type, material) = parse_public_key_line(P256).unwrap().unwrap().key_material().unwrap();
+        assert_eq!(key_type, KeyType::EcdsaP256);
+        assert_eq!(material.len(), 64);
+        assert_eq!(KeyType::EcdsaP256.key_to_limbs(&material).unwrap().len(), 8);
+
+        let (key_type, modulus) = parse_public_key_line(RSA).unwrap().unwrap().key_material().unwrap();
+        assert_eq!((key_type, modulus.len()), (KeyType::Rsa, 256));
+
+        // its fingerprint could not be told from the modulus alone
+        let other_exponent = PublicKey::from_blob(&rsa_blob(&[3], &modulus)).unwrap();
+        let err = other_exponent.key_material().unwrap_err();
+        assert!(err.to_string().contains("Unsupported RSA exponent 3"), "{err}");
+    }
+
+    #[test]
+    fn rsa_certificates_expose_the_certified_key() {
+        let plain = parse_public_key_line(RSA).unwrap().unwrap();
+        let cert = parse_public_key_line(RSA_CERT).unwrap().unwrap();
+        assert!(cert.is_certificate());
+        assert_eq!(cert.base_key_type(), "ssh-rsa");
+        assert_eq!(cert.rsa_components().unwrap(), plain.rsa_components().unwrap());
+        assert_eq!(plain.fingerprint(), "SHA256:OKNMr8Vi6B44z8cDFkR7TshzJIuyF+WVWYXRdFO6p0U");
+    }
+}
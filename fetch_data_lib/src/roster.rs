@@ -0,0 +1,202 @@
+//! Group definitions that name a GitHub organization or team instead of
+//! listing every member: a sender entry `org:0xPARC` stands for the members
+//! of the organization, `team:0xPARC/core` for the members of one of its
+//! teams. Any other entry is a username.
+//!
+//! Listings longer than `MAX_PAGES` pages are refused rather than cut short,
+//! since a partial group means wrong public signals.
+//!
+//! Membership is looked up through a [`MembershipSource`]; [`KeyServer`]
+//! implements it with GitHub's REST API (`/orgs/{org}/members` and
+//! `/orgs/{org}/teams/{team}/members`). Without a token the API only lists
+//! public organization members, and teams are not visible at all.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::{FetchError, KeyServer, Result};
+
+/// GitHub's largest page size for member listings.
+const PAGE_SIZE: usize = 100;
+/// Groups are capped at `MAX_GROUP_SIZE` keys anyway; larger listings are an error.
+const MAX_PAGES: usize = 10;
+
+pub type MembersFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<String>>> + Send + 'a>>;
+
+/// Looks up who belongs to an organization or team.
+pub trait MembershipSource: Send + Sync {
+    fn org_members<'a>(&'a self, org: &'a str) -> MembersFuture<'a>;
+    fn team_members<'a>(&'a self, org: &'a str, team: &'a str) -> MembersFuture<'a>;
+}
+
+/// One entry of a sender list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SenderRef {
+    User(String),
+    Org(String),
+    Team { org: String, team: String },
+}
+
+impl FromStr for SenderRef {
+    type Err = FetchError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        if let Some(org) = value.strip_prefix("org:") {
+            return Ok(SenderRef::Org(checked_name(org, value)?.to_string()));
+        }
+        if let Some(team) = value.strip_prefix("team:") {
+            let (org, team) = team
+                .split_once('/')
+                .ok_or_else(|| FetchError::invalid_input(format!("Invalid team '{}': expected 'team:<org>/<team>'", value)))?;
+            return Ok(SenderRef::Team {
+                org: checked_name(org, value)?.to_string(),
+                team: checked_name(team, value)?.to_string(),
+            });
+        }
+        Ok(SenderRef::User(checked_username(value)?.to_string()))
+    }
+}
+
+impl fmt::Display for SenderRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SenderRef::User(username) => write!(f, "{}", username),
+            SenderRef::Org(org) => write!(f, "org:{}", org),
+            SenderRef::Team { org, team } => write!(f, "team:{}/{}", org, team),
+        }
+    }
+}
+
+/// Organization and team names end up in URL paths, so only GitHub's own
+/// alphabet is accepted.
+fn checked_name<'a>(name: &'a str, entry: &str) -> Result<&'a str> {
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    match valid {
+        true => Ok(name),
+        false => Err(FetchError::invalid_input(format!("Invalid group reference '{}'", entry))),
+    }
+}
+
+/// Usernames end up in URL paths as well. GitHub usernames are letters,
+/// digits and dashes, and do not start with a dash.
+pub fn checked_username(username: &str) -> Result<&str> {
+    let valid = !username.starts_with('-') && !username.is_empty() && username.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    match valid {
+        true => Ok(username),
+        false => Err(FetchError::invalid_input(format!("Invalid GitHub username '{}'", username))),
+    }
+}
+
+/// Replaces every `org:`/`team:` entry of `senders` with the members it
+/// stands for. Order is kept and a member listed twice appears once.
+pub async fn resolve_senders(source: &dyn MembershipSource, senders: &[String]) -> Result<Vec<String>> {
+    let mut result: Vec<String> = Vec::new();
+    for sender in senders {
+        let members = match sender.parse::<SenderRef>()? {
+            SenderRef::User(username) => vec![username],
+            SenderRef::Org(org) => source.org_members(&org).await?,
+            SenderRef::Team { org, team } => source.team_members(&org, &team).await?,
+        };
+        for member in members {
+            if !result.contains(&member) {
+                result.push(member);
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[derive(Deserialize)]
+struct Member {
+    login: String,
+}
+
+impl KeyServer {
+    /// Pages through a GitHub member listing. `group` names the organization
+    /// or team in errors. A listing whose last allowed page is still full is
+    /// refused, as it may go on.
+    async fn fetch_members(&self, path: &str, group: &str) -> Result<Vec<String>> {
+        let mut result: Vec<String> = Vec::new();
+        for page in 1..=MAX_PAGES {
+            let address = format!("{}{}?per_page={}&page={}", self.api_url(), path, PAGE_SIZE, page);
+            let body = match self.http.get(&address, group, self.api_headers()?).await {
+                Err(FetchError::UserNotFound { .. }) => return Err(FetchError::GroupNotFound { group: group.to_string() }),
+                other => other?,
+            };
+            let members: Vec<Member> = serde_json::from_str(&body)
+                .map_err(|err| FetchError::invalid_input(format!("Invalid member list for '{}': {}", group, err)))?;
+            let last_page = members.len() < PAGE_SIZE;
+            result.extend(members.into_iter().map(|member| member.login));
+            if last_page {
+                return Ok(result);
+            }
+        }
+        Err(FetchError::MembershipTooLarge { group: group.to_string(), max: MAX_PAGES * PAGE_SIZE })
+    }
+}
+
+impl MembershipSource for KeyServer {
+    fn org_members<'a>(&'a self, org: &'a str) -> MembersFuture<'a> {
+        Box::pin(async move { self.fetch_members(&format!("orgs/{}/members", org), &format!("org:{}", org)).await })
+    }
+
+    fn team_members<'a>(&'a self, org: &'a str, team: &'a str) -> MembersFuture<'a> {
+        Box::pin(async move {
+            self.fetch_members(&format!("orgs/{}/teams/{}/members", org, team), &format!("team:{}/{}", org, team))
+                .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixed memberships: `parc` has alice, bob and carol, its team `core`
+    /// has bob and alice.
+    struct FixedMembers;
+
+    impl MembershipSource for FixedMembers {
+        fn org_members<'a>(&'a self, org: &'a str) -> MembersFuture<'a> {
+            Box::pin(async move {
+                match org {
+                    "parc" => Ok(vec!["alice".into(), "bob".into(), "carol".into()]),
+                    _ => Err(FetchError::GroupNotFound { group: format!("org:{}", org) }),
+                }
+            })
+        }
+
+        fn team_members<'a>(&'a self, _org: &'a str, _team: &'a str) -> MembersFuture<'a> {
+            Box::pin(async { Ok(vec!["bob".into(), "alice".into()]) })
+        }
+    }
+
+    fn senders(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_references() {
+        assert_eq!("org:0xPARC".parse::<SenderRef>().unwrap(), SenderRef::Org("0xPARC".into()));
+        let team: SenderRef = "team:0xPARC/core".parse().unwrap();
+        assert_eq!(team, SenderRef::Team { org: "0xPARC".into(), team: "core".into() });
+        assert_eq!(team.to_string(), "team:0xPARC/core");
+        assert_eq!("alice".parse::<SenderRef>().unwrap(), SenderRef::User("alice".into()));
+        for invalid in ["org:", "team:0xPARC", "team:0xPARC/", "org:../admin", "team:a/b/c", "", "../alice", "alice?page=2", "-alice"] {
+            assert!(invalid.parse::<SenderRef>().is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn expands_groups_without_duplicates() {
+        let resolved = resolve_senders(&FixedMembers, &senders(&["dave", "team:parc/core", "org:parc", "bob"])).await.unwrap();
+        assert_eq!(resolved, senders(&["dave", "bob", "alice", "carol"]));
+
+        let err = resolve_senders(&FixedMembers, &senders(&["org:nobody"])).await.unwrap_err();
+        assert_eq!(err, FetchError::GroupNotFound { group: "org:nobody".into() });
+    }
+}
@@ -0,0 +1,197 @@
+//! Import of RSA public keys kept outside of SSH: PEM encoded PKCS#1
+//! (`BEGIN RSA PUBLIC KEY`) and SubjectPublicKeyInfo (`BEGIN PUBLIC KEY`), and
+//! JSON Web Keys. Every format yields the same big-endian modulus and
+//! exponent as [`crate::extract_rsa_from_ssh`], so the keys are chunked into
+//! limbs by [`crate::modulus_to_limbs`] no matter where they came from.
+//!
+//! Keys whose exponent is not [`crate::RSA_EXPONENT`] are refused, as the
+//! circuit cannot prove over them. Imported keys join a group through
+//! [`crate::KeyFamiliesBuilder::add_imported_keys`].
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPublicKey;
+use serde_json::Value;
+
+use crate::error::malformed;
+use crate::{check_rsa_exponent, parse_public_key_line, Result};
+
+/// Modulus and exponent of an imported key, big-endian without leading zeros.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RsaComponents {
+    pub modulus: Vec<u8>,
+    pub exponent: Vec<u8>,
+}
+
+impl From<RsaPublicKey> for RsaComponents {
+    fn from(key: RsaPublicKey) -> Self {
+        Self {
+            modulus: key.n().to_bytes_be(),
+            exponent: key.e().to_bytes_be(),
+        }
+    }
+}
+
+impl RsaComponents {
+    fn checked(self) -> Result<Self> {
+        check_rsa_exponent(&self.exponent)?;
+        Ok(self)
+    }
+}
+
+/// Reads a PKCS#1 `RSA PUBLIC KEY` PEM block.
+pub fn rsa_from_pkcs1_pem(pem: &str) -> Result<RsaComponents> {
+    RsaPublicKey::from_pkcs1_pem(pem.trim())
+        .map(RsaComponents::from)
+        .map_err(|err| malformed!("Invalid PKCS#1 RSA public key: {}", err))?
+        .checked()
+}
+
+/// Reads a SubjectPublicKeyInfo `PUBLIC KEY` PEM block holding an RSA key.
+pub fn rsa_from_spki_pem(pem: &str) -> Result<RsaComponents> {
+    RsaPublicKey::from_public_key_pem(pem.trim())
+        .map(RsaComponents::from)
+        .map_err(|err| malformed!("Invalid SubjectPublicKeyInfo RSA public key: {}", err))?
+        .checked()
+}
+
+/// Reads an RSA JSON Web Key (`{"kty":"RSA","n":...,"e":...}`).
+pub fn rsa_from_jwk(jwk: &Value) -> Result<RsaComponents> {
+    if jwk.get("kty").and_then(Value::as_str) != Some("RSA") {
+        return Err(malformed!("Unsupported JWK: kty must be \"RSA\""));
+    }
+    let field = |name: &str| -> Result<Vec<u8>> {
+        let encoded = jwk
+            .get(name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| malformed!("Invalid JWK: missing \"{}\"", name))?;
+        let bytes = URL_SAFE_NO_PAD
+            .decode(encoded.trim_end_matches('='))
+            .map_err(|err| malformed!("Invalid JWK: \"{}\" is not base64url: {}", name, err))?;
+        let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len());
+        Ok(bytes[start..].to_vec())
+    };
+    let components = RsaComponents { modulus: field("n")?, exponent: field("e")? };
+    if components.modulus.is_empty() || components.exponent.is_empty() {
+        return Err(malformed!("Invalid JWK: zero modulus or exponent"));
+    }
+    components.checked()
+}
+
+/// Imports every RSA public key found in `text`, detecting the format: one or
+/// more PEM blocks, a JWK or JWK Set (`{"keys": [...]}`), or OpenSSH lines.
+pub fn import_rsa_public_keys(text: &str) -> Result<Vec<RsaComponents>> {
+    let trimmed = text.trim();
+    if trimmed.starts_with('{') {
+        let value: Value = serde_json::from_str(trimmed).map_err(|err| malformed!("Invalid JWK JSON: {}", err))?;
+        return match value.get("keys").and_then(Value::as_array) {
+            Some(keys) => keys.iter().map(rsa_from_jwk).collect(),
+            None => Ok(vec![rsa_from_jwk(&value)?]),
+        };
+    }
+    if trimmed.contains("-----BEGIN ") {
+        return pem_blocks(trimmed)?
+            .into_iter()
+            .map(|(label, block)| match label {
+                "RSA PUBLIC KEY" => rsa_from_pkcs1_pem(block),
+                "PUBLIC KEY" => rsa_from_spki_pem(block),
+                other => Err(malformed!("Unsupported PEM block: {}", other)),
+            })
+            .collect();
+    }
+    let mut result: Vec<RsaComponents> = Vec::new();
+    for line in trimmed.lines() {
+        if let Some(key) = parse_public_key_line(line)? {
+            let (modulus, exponent) = key.rsa_components()?;
+            result.push(RsaComponents { modulus, exponent }.checked()?);
+        }
+    }
+    Ok(result)
+}
+
+/// Splits `text` into `(label, block)` pairs, one per PEM block.
+fn pem_blocks(text: &str) -> Result<Vec<(&str, &str)>> {
+    let mut result: Vec<(&str, &str)> = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("-----BEGIN ") {
+        let label_start = start + "-----BEGIN ".len();
+        let label_len = rest[label_start..]
+            .find("-----")
+            .ok_or_else(|| malformed!("Invalid PEM: unterminated BEGIN line"))?;
+        let label = &rest[label_start..label_start + label_len];
+        let end_marker = format!("-----END {}-----", label);
+        let end = rest[start..]
+            .find(&end_marker)
+            .ok_or_else(|| malformed!("Invalid PEM: missing {}", end_marker))?
+            + start
+            + end_marker.len();
+        result.push((label, &rest[start..end]));
+        rest = &rest[end..];
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extract_rsa_from_ssh, modulus_to_limbs, FetchError};
+    use rsa::pkcs1::EncodeRsaPublicKey;
+    use rsa::pkcs8::EncodePublicKey;
+
+    fn public_key() -> RsaPublicKey {
+        let private = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        private.to_public_key()
+    }
+
+    #[test]
+    fn all_formats_agree() {
+        let key = public_key();
+        let expected = RsaComponents::from(key.clone());
+        let pkcs1 = key.to_pkcs1_pem(Default::default()).unwrap();
+        let spki = key.to_public_key_pem(Default::default()).unwrap();
+        let jwk = format!(
+            r#"{{"kty":"RSA","kid":"k1","n":"{}","e":"{}"}}"#,
+            URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+            URL_SAFE_NO_PAD.encode(key.e().to_bytes_be())
+        );
+
+        assert_eq!(rsa_from_pkcs1_pem(&pkcs1).unwrap(), expected);
+        assert_eq!(rsa_from_spki_pem(&spki).unwrap(), expected);
+        assert_eq!(import_rsa_public_keys(&jwk).unwrap(), vec![expected.clone()]);
+        assert_eq!(
+            import_rsa_public_keys(&format!("{pkcs1}\n{spki}")).unwrap(),
+            vec![expected.clone(), expected.clone()]
+        );
+        assert_eq!(
+            modulus_to_limbs(&import_rsa_public_keys(&jwk).unwrap()[0].modulus).unwrap(),
+            modulus_to_limbs(&expected.modulus).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn openssh_lines_match_extract_rsa_from_ssh() {
+        let line = include_str!("../fixtures/alice.pub");
+        let (modulus, exponent) = extract_rsa_from_ssh(line).await.unwrap();
+        assert_eq!(import_rsa_public_keys(line).unwrap(), vec![RsaComponents { modulus, exponent }]);
+    }
+
+    #[test]
+    fn rejects_foreign_keys() {
+        assert!(import_rsa_public_keys(r#"{"kty":"EC","crv":"P-256","x":"AA","y":"AA"}"#).is_err());
+        assert!(import_rsa_public_keys(r#"{"keys":[{"kty":"RSA","e":"AQAB"}]}"#).is_err());
+        assert!(import_rsa_public_keys("-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----").is_err());
+        assert!(rsa_from_pkcs1_pem("-----BEGIN RSA PUBLIC KEY-----\nAAAA\n-----END RSA PUBLIC KEY-----").is_err());
+    }
+
+    #[test]
+    fn rejects_exponents_the_circuit_cannot_use() {
+        let private = rsa::RsaPrivateKey::new_with_exp(&mut rand::thread_rng(), 1024, &rsa::BigUint::from(3u32)).unwrap();
+        let pkcs1 = private.to_public_key().to_pkcs1_pem(Default::default()).unwrap();
+        let err = rsa_from_pkcs1_pem(&pkcs1).unwrap_err();
+        assert!(matches!(err, FetchError::MalformedKey { .. }) && err.to_string().contains("exponent 3"), "{err}");
+        assert!(import_rsa_public_keys(r#"{"kty":"RSA","n":"AQAB","e":"Aw"}"#).is_err());
+    }
+}
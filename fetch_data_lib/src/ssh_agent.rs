@@ -0,0 +1,248 @@
+//! Witness generation backed by a running `ssh-agent`, so members never have
+//! to export their RSA private key to a file.
+//!
+//! Only the tiny subset of the agent protocol
+//! (draft-miller-ssh-agent) needed here is implemented: listing identities
+//! and requesting an `rsa-sha2-512` signature. The agent hashes the data
+//! itself, so we send it the canonical message encoding and get back a plain
+//! RSASSA-PKCS1-v1_5/SHA-512 signature, which is what the circuit checks.
+
+use std::path::Path;
+
+use num_bigint::BigUint;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+use crate::openssh::WireReader;
+use crate::{build_circuit_input_from_signature, CanonicalMessage, CircuitInput, FetchError, GroupKeySet, PublicKey, Result};
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+/// Upper bound for a single agent reply, to avoid allocating whatever length a
+/// misbehaving peer announces.
+const MAX_REPLY_LEN: usize = 256 * 1024;
+
+/// [`FetchError::Agent`] with a `format!`ted reason.
+macro_rules! agent_error {
+    ($($arg:tt)*) => {
+        FetchError::Agent { reason: format!($($arg)*) }
+    };
+}
+
+/// An RSA key held by the agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentIdentity {
+    /// The SSH wire-format public key blob, used to address the key.
+    pub key_blob: Vec<u8>,
+    pub comment: String,
+    /// Big-endian RSA modulus.
+    pub modulus: Vec<u8>,
+}
+
+/// Connection to an ssh-agent over its UNIX socket.
+pub struct SshAgentClient {
+    stream: UnixStream,
+}
+
+impl SshAgentClient {
+    /// Connects to the agent named by the `SSH_AUTH_SOCK` environment variable.
+    pub async fn connect_env() -> Result<Self> {
+        let path = std::env::var("SSH_AUTH_SOCK")
+            .map_err(|_| agent_error!("SSH_AUTH_SOCK is not set: is ssh-agent running?"))?;
+        Self::connect(path).await
+    }
+
+    pub async fn connect(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let stream = UnixStream::connect(path)
+            .await
+            .map_err(|err| agent_error!("Cannot connect to ssh-agent at {}: {}", path.display(), err))?;
+        Ok(Self { stream })
+    }
+
+    /// Lists the RSA keys loaded in the agent; other key types are skipped.
+    pub async fn rsa_identities(&mut self) -> Result<Vec<AgentIdentity>> {
+        let reply = self.request(SSH_AGENTC_REQUEST_IDENTITIES, &[]).await?;
+        let mut reader = reply_reader(&reply, SSH_AGENT_IDENTITIES_ANSWER)?;
+        let count = reader.u32()?;
+        let mut result: Vec<AgentIdentity> = Vec::new();
+        for _ in 0..count {
+            let key_blob = reader.string()?.to_vec();
+            let comment = String::from_utf8_lossy(reader.string()?).into_owned();
+            let key = PublicKey::from_blob(&key_blob)?;
+            if key.key_type != "ssh-rsa" {
+                continue;
+            }
+            let (modulus, _exponent) = key.rsa_components()?;
+            result.push(AgentIdentity { key_blob, comment, modulus });
+        }
+        Ok(result)
+    }
+
+    /// Asks the agent for an `rsa-sha2-512` signature over `data` and returns
+    /// the raw signature bytes.
+    pub async fn sign_rsa_sha2_512(&mut self, identity: &AgentIdentity, data: &[u8]) -> Result<Vec<u8>> {
+        let mut payload: Vec<u8> = Vec::new();
+        put_string(&mut payload, &identity.key_blob);
+        put_string(&mut payload, data);
+        payload.extend_from_slice(&SSH_AGENT_RSA_SHA2_512.to_be_bytes());
+
+        let reply = self.request(SSH_AGENTC_SIGN_REQUEST, &payload).await?;
+        let mut reader = reply_reader(&reply, SSH_AGENT_SIGN_RESPONSE)?;
+        let mut signature = WireReader::new(reader.string()?);
+        let algorithm = signature.string()?;
+        if algorithm != b"rsa-sha2-512" {
+            return Err(agent_error!(
+                "ssh-agent returned a '{}' signature instead of rsa-sha2-512",
+                String::from_utf8_lossy(algorithm)
+            ));
+        }
+        Ok(signature.string()?.to_vec())
+    }
+
+    async fn request(&mut self, kind: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut message: Vec<u8> = Vec::with_capacity(payload.len() + 5);
+        message.extend_from_slice(&(payload.len() as u32 + 1).to_be_bytes());
+        message.push(kind);
+        message.extend_from_slice(payload);
+        self.stream.write_all(&message).await.map_err(io_error)?;
+
+        let len = self.stream.read_u32().await.map_err(io_error)? as usize;
+        if len == 0 || len > MAX_REPLY_LEN {
+            return Err(agent_error!("ssh-agent sent a reply of invalid length {}", len));
+        }
+        let mut reply = vec![0u8; len];
+        self.stream.read_exact(&mut reply).await.map_err(io_error)?;
+        Ok(reply)
+    }
+}
+
+/// Builds the circuit input using the first agent key that belongs to
+/// `key_set` to sign the canonical encoding of `message`.
+pub async fn build_circuit_input_with_agent(
+    agent: &mut SshAgentClient,
+    key_set: &GroupKeySet,
+    message: &CanonicalMessage,
+) -> Result<CircuitInput> {
+    let identities = agent.rsa_identities().await?;
+    let identity = identities
+        .iter()
+        .find(|identity| {
+            let n = BigUint::from_bytes_be(&identity.modulus);
+            key_set.keys().iter().any(|key| BigUint::from_bytes_be(&key.public_key) == n)
+        })
+        .ok_or_else(|| FetchError::invalid_input(format!("None of the {} RSA keys in ssh-agent is part of the group", identities.len())))?;
+    let signature = agent.sign_rsa_sha2_512(identity, &message.encode()).await?;
+    build_circuit_input_from_signature(&identity.modulus, &signature, key_set, message)
+}
+
+fn io_error(err: std::io::Error) -> FetchError {
+    agent_error!("Connection to ssh-agent failed: {}", err)
+}
+
+fn put_string(out: &mut Vec<u8>, value: &[u8]) {
+    out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+/// Checks the reply type byte and returns a reader positioned after it.
+fn reply_reader(reply: &[u8], expected: u8) -> Result<WireReader<'_>> {
+    match reply.first() {
+        Some(kind) if *kind == expected => Ok(WireReader::new(&reply[1..])),
+        Some(&SSH_AGENT_FAILURE) => Err(agent_error!("ssh-agent refused the request")),
+        Some(kind) => Err(agent_error!("Unexpected ssh-agent reply type {}", kind)),
+        None => Err(agent_error!("Empty ssh-agent reply")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{convert_publicSignals, create_pb_signals_from_key_set, extract_rsa_from_ssh, GroupKeySetBuilder};
+    use std::process::{Child, Command, Stdio};
+
+    /// A throwaway `ssh-agent` with one freshly generated RSA key.
+    struct TestAgent {
+        child: Child,
+        socket: std::path::PathBuf,
+        public_key: String,
+        _dir: tempfile::TempDir,
+    }
+
+    impl Drop for TestAgent {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    /// Starts a throwaway ssh-agent holding a fresh RSA key. Needs OpenSSH's
+    /// `ssh-keygen`, `ssh-agent` and `ssh-add`; without them the tests fail
+    /// rather than pass without running.
+    fn start_agent() -> TestAgent {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("agent.sock");
+        let key = dir.path().join("id_rsa");
+        let keygen = Command::new("ssh-keygen")
+            .args(["-q", "-t", "rsa", "-b", "2048", "-N", "", "-f"])
+            .arg(&key)
+            .status()
+            .expect("ssh-keygen is needed to run the ssh-agent tests");
+        assert!(keygen.success(), "ssh-keygen failed");
+        let child = Command::new("ssh-agent")
+            .arg("-D")
+            .arg("-a")
+            .arg(&socket)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("ssh-agent is needed to run the ssh-agent tests");
+        for _ in 0..50 {
+            if socket.exists() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert!(socket.exists(), "ssh-agent did not create its socket");
+        let added = Command::new("ssh-add").arg("-q").arg(&key).env("SSH_AUTH_SOCK", &socket).status();
+        assert!(matches!(added, Ok(status) if status.success()), "ssh-add failed");
+        let public_key = std::fs::read_to_string(key.with_extension("pub")).unwrap();
+        TestAgent { child, socket, public_key, _dir: dir }
+    }
+
+    #[tokio::test]
+    async fn signs_canonical_message_through_the_agent() {
+        let agent = start_agent();
+        let (modulus, _) = extract_rsa_from_ssh(&agent.public_key).await.unwrap();
+        let mut builder = GroupKeySetBuilder::new();
+        builder.add_key("alice", &modulus).add_key("bob", &[0x0b; 256]);
+        let key_set = builder.build().unwrap();
+        let message = CanonicalMessage::new(Some("team@example.org".to_string()), "Kudos", "Great demo", None);
+
+        let mut client = SshAgentClient::connect(&agent.socket).await.unwrap();
+        let identities = client.rsa_identities().await.unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].modulus, modulus);
+
+        let input = build_circuit_input_with_agent(&mut client, &key_set, &message).await.unwrap();
+        let server = convert_publicSignals(create_pb_signals_from_key_set(&key_set, &message).unwrap()).await;
+        assert_eq!(input.public_signals(), server);
+    }
+
+    #[tokio::test]
+    async fn fails_when_no_agent_key_is_in_the_group() {
+        let agent = start_agent();
+        let mut builder = GroupKeySetBuilder::new();
+        builder.add_key("bob", &[0x0b; 256]);
+        let key_set = builder.build().unwrap();
+        let message = CanonicalMessage::new(None, "Kudos", "Great demo", None);
+
+        let mut client = SshAgentClient::connect(&agent.socket).await.unwrap();
+        let err = build_circuit_input_with_agent(&mut client, &key_set, &message).await.unwrap_err();
+        assert!(err.to_string().contains("None of"), "{err}");
+    }
+}
@@ -0,0 +1,188 @@
+//! OpenSSH signatures (`ssh-keygen -Y sign`, the `SSHSIG` format), used by
+//! members to prove control of one of their published keys:
+//!
+//! ```text
+//! printf '%s' "$CHALLENGE" | ssh-keygen -Y sign -n <namespace> -f ~/.ssh/id_rsa
+//! ```
+//!
+//! Only RSA keys (`rsa-sha2-512` and `rsa-sha2-256` signatures) are verified,
+//! the family the circuit proves over.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use num_bigint::BigUint;
+use rsa::{Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+use rsa::traits::PublicKeyParts;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::openssh::{put_string, rsa_blob, WireReader};
+use crate::{FetchError, KeyServer, KeyType, PublicKey, Result};
+
+const MAGIC: &[u8] = b"SSHSIG";
+const VERSION: u32 = 1;
+const BEGIN: &str = "-----BEGIN SSH SIGNATURE-----";
+const END: &str = "-----END SSH SIGNATURE-----";
+
+fn signature_error(reason: impl Into<String>) -> FetchError {
+    FetchError::Signature { reason: reason.into() }
+}
+
+/// The data an `SSHSIG` signature is made over.
+fn signed_data(namespace: &str, hash_algorithm: &str, message: &[u8]) -> Result<Vec<u8>> {
+    let message_hash = match hash_algorithm {
+        "sha512" => Sha512::digest(message).to_vec(),
+        "sha256" => Sha256::digest(message).to_vec(),
+        other => return Err(signature_error(format!("Unsupported signature hash '{}'", other))),
+    };
+    let mut data = MAGIC.to_vec();
+    put_string(&mut data, namespace.as_bytes());
+    put_string(&mut data, b"");
+    put_string(&mut data, hash_algorithm.as_bytes());
+    put_string(&mut data, &message_hash);
+    Ok(data)
+}
+
+/// Checks an armored `SSHSIG` signature over `message` made for `namespace`
+/// and returns the key that made it.
+pub fn verify_ssh_signature(armored: &str, namespace: &str, message: &[u8]) -> Result<PublicKey> {
+    let body = armored.trim();
+    let body = body
+        .strip_prefix(BEGIN)
+        .and_then(|body| body.strip_suffix(END))
+        .ok_or_else(|| signature_error("Not an SSH signature: expected the output of 'ssh-keygen -Y sign'"))?;
+    let blob: String = body.split_whitespace().collect();
+    let blob = STANDARD.decode(blob).map_err(|err| signature_error(format!("Invalid SSH signature encoding: {}", err)))?;
+
+    let malformed = |err: FetchError| signature_error(format!("Malformed SSH signature: {}", err));
+    if !blob.starts_with(MAGIC) {
+        return Err(signature_error("Malformed SSH signature: missing SSHSIG preamble"));
+    }
+    let mut reader = WireReader::new(&blob[MAGIC.len()..]);
+    let version = reader.u32().map_err(malformed)?;
+    if version != VERSION {
+        return Err(signature_error(format!("Unsupported SSH signature version {}", version)));
+    }
+    let public_key = PublicKey::from_blob(reader.string().map_err(malformed)?)?;
+    let signed_namespace = reader.string().map_err(malformed)?;
+    let _reserved = reader.string().map_err(malformed)?;
+    let hash_algorithm = String::from_utf8_lossy(reader.string().map_err(malformed)?).into_owned();
+    let mut signature = WireReader::new(reader.string().map_err(malformed)?);
+    if signed_namespace != namespace.as_bytes() {
+        return Err(signature_error(format!(
+            "Signature was made for namespace '{}', expected '{}'",
+            String::from_utf8_lossy(signed_namespace),
+            namespace
+        )));
+    }
+
+    let algorithm = signature.string().map_err(malformed)?;
+    let signature = signature.string().map_err(malformed)?;
+    if public_key.base_key_type() != "ssh-rsa" {
+        return Err(signature_error(format!("Only RSA keys can sign, got a {} key", public_key.key_type)));
+    }
+    let (modulus, exponent) = public_key.rsa_components()?;
+    let rsa_key = RsaPublicKey::new(rsa::BigUint::from_bytes_be(&modulus), rsa::BigUint::from_bytes_be(&exponent))
+        .map_err(|err| signature_error(format!("Invalid RSA key: {}", err)))?;
+    let data = signed_data(namespace, &hash_algorithm, message)?;
+    let verified = match algorithm {
+        b"rsa-sha2-512" => rsa_key.verify(Pkcs1v15Sign::new::<Sha512>(), &Sha512::digest(&data), signature),
+        b"rsa-sha2-256" => rsa_key.verify(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(&data), signature),
+        other => return Err(signature_error(format!("Unsupported signature algorithm '{}'", String::from_utf8_lossy(other)))),
+    };
+    verified.map_err(|_| signature_error("SSH signature does not verify"))?;
+    Ok(public_key)
+}
+
+/// Signs `message` for `namespace` the way `ssh-keygen -Y sign` does with an
+/// RSA key, for clients that hold the private key themselves.
+pub fn sign_ssh_signature(private_key: &RsaPrivateKey, namespace: &str, message: &[u8]) -> Result<String> {
+    let key_blob = rsa_blob(&private_key.e().to_bytes_be(), &private_key.n().to_bytes_be());
+
+    let data = signed_data(namespace, "sha512", message)?;
+    let raw_signature = private_key
+        .sign(Pkcs1v15Sign::new::<Sha512>(), &Sha512::digest(&data))
+        .map_err(|err| signature_error(format!("Cannot sign: {}", err)))?;
+    let mut signature: Vec<u8> = Vec::new();
+    put_string(&mut signature, b"rsa-sha2-512");
+    put_string(&mut signature, &raw_signature);
+
+    let mut blob = MAGIC.to_vec();
+    blob.extend_from_slice(&VERSION.to_be_bytes());
+    put_string(&mut blob, &key_blob);
+    put_string(&mut blob, namespace.as_bytes());
+    put_string(&mut blob, b"");
+    put_string(&mut blob, b"sha512");
+    put_string(&mut blob, &signature);
+
+    let encoded = STANDARD.encode(blob);
+    let mut armored = format!("{}\n", BEGIN);
+    // ssh-keygen wraps at 70 columns
+    for line in encoded.as_bytes().chunks(70) {
+        armored.push_str(std::str::from_utf8(line).unwrap());
+        armored.push('\n');
+    }
+    armored.push_str(END);
+    armored.push('\n');
+    Ok(armored)
+}
+
+impl KeyServer {
+    /// Verifies an `SSHSIG` signature and checks that it was made with one of
+    /// the RSA keys `username` publishes.
+    pub async fn verify_user_signature(
+        &self,
+        username: &str,
+        armored: &str,
+        namespace: &str,
+        message: &[u8],
+    ) -> Result<PublicKey> {
+        let public_key = verify_ssh_signature(armored, namespace, message)?;
+        let (modulus, _) = public_key.rsa_components()?;
+        let modulus = BigUint::from_bytes_be(&modulus);
+        let published = self.fetch_keys(username).await?;
+        let owned = published
+            .keys
+            .iter()
+            .any(|(key_type, key)| *key_type == KeyType::Rsa && BigUint::from_bytes_be(key) == modulus);
+        match owned {
+            true => Ok(public_key),
+            false => Err(signature_error(format!("The signing key is not one of the keys '{}' publishes", username))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_public_key_line;
+
+    const NAMESPACE: &str = "send-group-emails-consent";
+    const MESSAGE: &[u8] = b"opt in as dave";
+    /// Made with `ssh-keygen -Y sign -n send-group-emails-consent` over
+    /// "opt in as dave", by the keys of `dave.pub` and an Ed25519 key.
+    const SIGNATURE: &str = include_str!("../fixtures/dave.sig");
+    const ED25519_SIGNATURE: &str = include_str!("../fixtures/ed25519.sig");
+    const PUBLIC_KEY: &str = include_str!("../fixtures/dave.pub");
+
+    #[test]
+    fn verifies_ssh_keygen_signatures() {
+        let signer = verify_ssh_signature(SIGNATURE, NAMESPACE, MESSAGE).unwrap();
+        assert_eq!(signer.blob, parse_public_key_line(PUBLIC_KEY).unwrap().unwrap().blob);
+
+        assert!(verify_ssh_signature(SIGNATURE, NAMESPACE, b"opt in as eve").is_err());
+        let err = verify_ssh_signature(SIGNATURE, "file", MESSAGE).unwrap_err();
+        assert!(err.to_string().contains("namespace"), "{err}");
+        let err = verify_ssh_signature(ED25519_SIGNATURE, NAMESPACE, MESSAGE).unwrap_err();
+        assert!(err.to_string().contains("Only RSA keys"), "{err}");
+        assert!(verify_ssh_signature("not a signature", NAMESPACE, MESSAGE).is_err());
+    }
+
+    #[test]
+    fn signatures_round_trip() {
+        let mut rng = rand::thread_rng();
+        let private_key = RsaPrivateKey::new(&mut rng, 1024).unwrap();
+        let armored = sign_ssh_signature(&private_key, NAMESPACE, MESSAGE).unwrap();
+        let signer = verify_ssh_signature(&armored, NAMESPACE, MESSAGE).unwrap();
+        let (modulus, _) = signer.rsa_components().unwrap();
+        assert_eq!(BigUint::from_bytes_be(&modulus), BigUint::from_bytes_be(&private_key.n().to_bytes_be()));
+    }
+}
@@ -0,0 +1,175 @@
+//! Builds the private and public inputs of the `GroupSignature` circuit
+//! (`input.json` for snarkjs) from a member's RSA key, the group key set and
+//! the canonical message.
+//!
+//! The public part is produced by the same code path the server uses
+//! (`create_pb_signals_from_key_set`), so a proof generated from this input
+//! verifies against the server's `publicSignals` byte-for-byte.
+
+use num_bigint::BigUint;
+use rsa::traits::PublicKeyParts;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::{Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::Sha512;
+
+use crate::{convert_byte_to_chunks, create_pb_signals_from_key_set, CanonicalMessage, FetchError, GroupKeySet, KeyType, Result, RSA_EXPONENT};
+
+/// Full input of the `GroupSignature` circuit, with every limb written as a
+/// decimal string as snarkjs expects.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitInput {
+    pub message: Vec<String>,
+    pub double_blind_hash: Vec<String>,
+    pub keys: Vec<Vec<String>>,
+    pub double_blind_signature: Vec<String>,
+    #[serde(rename = "correctKey")]
+    pub correct_key: Vec<String>,
+}
+
+impl CircuitInput {
+    /// The public inputs in the order the verifier expects them; equal to what
+    /// the server derives from the submission.
+    pub fn public_signals(&self) -> Vec<String> {
+        let mut result = self.message.clone();
+        for key in &self.keys {
+            result.extend(key.iter().cloned());
+        }
+        result
+    }
+
+    /// Serializes the input as the `input.json` file consumed by snarkjs.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(|err| FetchError::invalid_input(format!("Cannot serialize circuit input: {}", err)))
+    }
+}
+
+/// Reads an RSA private key in PKCS#1 (`BEGIN RSA PRIVATE KEY`, e.g. from
+/// `ssh-keygen -m PEM`) or PKCS#8 (`BEGIN PRIVATE KEY`) PEM form.
+pub fn load_private_key_pem(pem: &str) -> Result<RsaPrivateKey> {
+    RsaPrivateKey::from_pkcs1_pem(pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs8_pem(pem))
+        .map_err(|err| FetchError::invalid_input(format!("Unable to read RSA private key: {}", err)))
+}
+
+/// Signs `message` with `private_key` (RSASSA-PKCS1-v1_5 over SHA-512) and
+/// assembles the circuit input. The key's modulus must be part of `key_set`.
+pub fn build_circuit_input(
+    private_key: &RsaPrivateKey,
+    key_set: &GroupKeySet,
+    message: &CanonicalMessage,
+) -> Result<CircuitInput> {
+    let signature = private_key
+        .sign(Pkcs1v15Sign::new::<Sha512>(), &message.hash())
+        .map_err(|err| FetchError::Signature { reason: format!("Failed to sign the message: {}", err) })?;
+    build_circuit_input_from_signature(&private_key.n().to_bytes_be(), &signature, key_set, message)
+}
+
+/// Assembles the circuit input from an existing RSASSA-PKCS1-v1_5/SHA-512
+/// `signature` over the canonical encoding of `message`, made by the key with
+/// big-endian `modulus`. The signature is checked before anything is returned.
+pub fn build_circuit_input_from_signature(
+    modulus: &[u8],
+    signature: &[u8],
+    key_set: &GroupKeySet,
+    message: &CanonicalMessage,
+) -> Result<CircuitInput> {
+    if key_set.key_type() != KeyType::Rsa {
+        return Err(FetchError::invalid_input(format!(
+            "The GroupSignature circuit needs an RSA key set, got {}",
+            key_set.key_type()
+        )));
+    }
+    let n = BigUint::from_bytes_be(modulus);
+    let correct_key = key_set
+        .keys()
+        .iter()
+        .find(|key| BigUint::from_bytes_be(&key.public_key) == n)
+        .ok_or_else(|| FetchError::invalid_input("The signing key is not part of the group key set"))?;
+
+    let public_key = RsaPublicKey::new(
+        rsa::BigUint::from_bytes_be(modulus),
+        rsa::BigUint::from(RSA_EXPONENT),
+    )
+    .map_err(|err| FetchError::malformed(format!("Invalid RSA public key: {}", err)))?;
+    public_key
+        .verify(Pkcs1v15Sign::new::<Sha512>(), &message.hash(), signature)
+        .map_err(|_| FetchError::Signature {
+            reason: "The signature does not match the message and the signing key".to_string(),
+        })?;
+
+    // `double_blind_hash` is the padded digest the signature opens to.
+    let s = BigUint::from_bytes_be(signature);
+    let padded_digest = s.modpow(&BigUint::from(RSA_EXPONENT), &n);
+
+    let pb_signals = create_pb_signals_from_key_set(key_set, message)?;
+    let keys: Vec<Vec<String>> = pb_signals.keys.iter().map(|key| to_strings(key)).collect();
+
+    Ok(CircuitInput {
+        message: to_strings(&pb_signals.message_hash),
+        double_blind_hash: to_strings(&convert_byte_to_chunks(120, 35, &padded_digest.to_bytes_be())?),
+        keys,
+        double_blind_signature: to_strings(&convert_byte_to_chunks(120, 35, signature)?),
+        correct_key: to_strings(&correct_key.limbs),
+    })
+}
+
+fn to_strings(limbs: &[u128]) -> Vec<String> {
+    limbs.iter().map(|limb| limb.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{convert_publicSignals, GroupKeySetBuilder};
+
+    fn private_key() -> RsaPrivateKey {
+        let mut rng = rand::thread_rng();
+        RsaPrivateKey::new_with_exp(&mut rng, 1024, &rsa::BigUint::from(RSA_EXPONENT)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn public_part_matches_server_signals() {
+        let key = private_key();
+        let mut builder = GroupKeySetBuilder::new();
+        builder.add_key("alice", &key.n().to_bytes_be()).add_key("bob", &[0x0b; 128]);
+        let key_set = builder.build().unwrap();
+        let message = CanonicalMessage::new(None, "Kudos", "Thanks for the talk", Some(42));
+
+        let input = build_circuit_input(&key, &key_set, &message).unwrap();
+        let server = convert_publicSignals(create_pb_signals_from_key_set(&key_set, &message).unwrap()).await;
+        assert_eq!(input.public_signals(), server);
+        assert_eq!(input.correct_key, to_strings(&convert_byte_to_chunks(120, 35, &key.n().to_bytes_be()).unwrap()));
+        assert!(input.to_json().unwrap().contains("\"correctKey\""));
+    }
+
+    #[test]
+    fn loads_pkcs1_and_pkcs8_keys() {
+        use rsa::pkcs1::EncodeRsaPrivateKey;
+        use rsa::pkcs8::EncodePrivateKey;
+
+        let key = private_key();
+        let pkcs1 = key.to_pkcs1_pem(Default::default()).unwrap();
+        let pkcs8 = key.to_pkcs8_pem(Default::default()).unwrap();
+        assert_eq!(load_private_key_pem(&pkcs1).unwrap(), key);
+        assert_eq!(load_private_key_pem(&pkcs8).unwrap(), key);
+        assert!(load_private_key_pem("not a key").is_err());
+    }
+
+    #[test]
+    fn rejects_keys_outside_the_group_and_bad_signatures() {
+        let key = private_key();
+        let mut builder = GroupKeySetBuilder::new();
+        builder.add_key("bob", &[0x0b; 128]);
+        let key_set = builder.build().unwrap();
+        let message = CanonicalMessage::new(None, "Kudos", "Thanks", None);
+        assert!(build_circuit_input(&key, &key_set, &message).is_err());
+
+        let mut builder = GroupKeySetBuilder::new();
+        builder.add_key("alice", &key.n().to_bytes_be());
+        let key_set = builder.build().unwrap();
+        let modulus = key.n().to_bytes_be();
+        assert!(build_circuit_input_from_signature(&modulus, &[1; 128], &key_set, &message).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! Admin endpoints for named groups and the blocklist. Saving a group resolves its `org:` and
+//! `team:` entries and pins the members' current keys; submissions naming the
+//! group are checked against those keys until the group is saved again.
+//! Blocklist changes take effect on the next submission and are audited.
+//!
+//! Every request needs `Authorization: Bearer <ADMIN_TOKEN>`. Without a
+//! configured token the endpoints are disabled.
+
+use axum::{extract::{Json, Path, State}, http::{HeaderMap, StatusCode, header::AUTHORIZATION}, response::{IntoResponse, Response}};
+use serde::{Deserialize, Deserializer, Serialize};
+use fetch_data_lib::{resolve_senders, FetchError, MAX_GROUP_SIZE};
+use database_lib::{add_block, block_audit_trail, get_group, list_blocks, list_groups, remove_block, save_group, BlockAuditEntry, BlockEntry, BlockKind, Group};
+use chrono::Utc;
+
+use super::{family_keys, fetch_error_response, fetch_key_families, record_key_history, with_connection, AppState};
+
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct NewGroup{
+    pub name: String,
+    // usernames, `org:<org>` or `team:<org>/<team>`
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub recipient: Option<String>,
+    // caps the distinct keys, defaults to MAX_GROUP_SIZE; proofs always use that circuit
+    #[serde(default)]
+    pub max_keys: Option<usize>,
+    // raise the server's anonymity minimums for this group
+    #[serde(default)]
+    pub min_members: Option<usize>,
+    #[serde(default)]
+    pub min_keys: Option<usize>,
+}
+
+// fields left out keep their value and `null` clears the optional ones; the keys are pinned again either way
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq, Default)]
+pub struct GroupChanges{
+    #[serde(default)]
+    pub members: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub recipient: Option<Option<String>>,
+    #[serde(default)]
+    pub max_keys: Option<usize>,
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub min_members: Option<Option<usize>>,
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub min_keys: Option<Option<usize>>,
+}
+
+// tells a field sent as `null` (Some(None)) apart from one left out (None)
+fn present<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<Option<T>, D::Error>{
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct BlockRequest{
+    pub kind: BlockKind,
+    // a username, or a key fingerprint as printed by `ssh-keygen -l`
+    pub value: String,
+    // kept in the audit trail
+    pub reason: String,
+}
+
+fn error(status: StatusCode, reason: &str) -> Response{
+    (status, format!("Sorry, {reason}")).into_response()
+}
+
+fn database_error(err: rusqlite::Error) -> Response{
+    eprintln!("Database error: {err}");
+    error(StatusCode::INTERNAL_SERVER_ERROR, "the database could not be read or written.")
+}
+
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)>{
+    let Some(token) = &state.admin_token else {
+        return Err((StatusCode::FORBIDDEN, "Sorry, admin endpoints are disabled."));
+    };
+    let given = headers.get(AUTHORIZATION).and_then(|value| value.to_str().ok()).and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => Ok(()),
+        _ => Err((StatusCode::UNAUTHORIZED, "Sorry, a valid admin token is required.")),
+    }
+}
+
+// does not stop at the first differing byte, so the token cannot be guessed byte by byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool{
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn valid_name(name: &str) -> bool{
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+// resolves the members, pins their keys and stores the group
+async fn pin_and_save(state: &AppState, mut group: Group) -> Result<Group, Response>{
+    if group.max_keys == 0 || group.max_keys > MAX_GROUP_SIZE {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, &format!("the key limit must be between 1 and {MAX_GROUP_SIZE}.")));
+    }
+    group.members = resolve_senders(&state.key_server, &group.members).await.map_err(fetch_error_response)?;
+    let families = fetch_key_families(&state.key_server, &group.members).await.map_err(fetch_error_response)?;
+    for key_type in families.key_types() {
+        let keys = families.get(key_type).map_or(0, |set| set.len());
+        if keys > group.max_keys {
+            return Err(fetch_error_response(FetchError::GroupTooLarge{keys, max: group.max_keys}));
+        }
+    }
+    let saved = group.clone();
+    with_connection(&state.database, move |conn| {
+        if let Err(err) = record_key_history(conn, &families, Utc::now().timestamp()) {
+            eprintln!("Failed to record key history: {err}");
+        }
+        save_group(conn, &saved, &family_keys(&families))
+    }).await.map_err(database_error)?;
+    Ok(group)
+}
+
+pub async fn list(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<Vec<Group>>, Response>{
+    authorize(&state, &headers).map_err(IntoResponse::into_response)?;
+    let groups = with_connection(&state.database, list_groups).await.map_err(database_error)?;
+    Ok(Json(groups))
+}
+
+pub async fn create(State(state): State<AppState>, headers: HeaderMap, Json(new_group): Json<NewGroup>) -> Result<(StatusCode, Json<Group>), Response>{
+    authorize(&state, &headers).map_err(IntoResponse::into_response)?;
+    if !valid_name(&new_group.name) {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "group names may only contain letters, digits, '-', '_' and '.'."));
+    }
+    let name = new_group.name.clone();
+    let existing = with_connection(&state.database, move |conn| get_group(conn, &name)).await.map_err(database_error)?;
+    if existing.is_some() {
+        return Err(error(StatusCode::CONFLICT, &format!("group '{}' already exists.", new_group.name)));
+    }
+    let group = Group{
+        name: new_group.name,
+        members: new_group.members,
+        recipient: new_group.recipient,
+        max_keys: new_group.max_keys.unwrap_or(MAX_GROUP_SIZE),
+        min_members: new_group.min_members,
+        min_keys: new_group.min_keys,
+    };
+    Ok((StatusCode::CREATED, Json(pin_and_save(&state, group).await?)))
+}
+
+pub async fn edit(State(state): State<AppState>, headers: HeaderMap, Path(name): Path<String>, Json(changes): Json<GroupChanges>) -> Result<Json<Group>, Response>{
+    authorize(&state, &headers).map_err(IntoResponse::into_response)?;
+    let existing = with_connection(&state.database, { let name = name.clone(); move |conn| get_group(conn, &name) }).await.map_err(database_error)?;
+    let Some(mut group) = existing else {
+        return Err(error(StatusCode::NOT_FOUND, &format!("there is no group '{name}'.")));
+    };
+    if let Some(members) = changes.members {
+        group.members = members;
+    }
+    if let Some(recipient) = changes.recipient {
+        group.recipient = recipient;
+    }
+    if let Some(max_keys) = changes.max_keys {
+        group.max_keys = max_keys;
+    }
+    if let Some(min_members) = changes.min_members {
+        group.min_members = min_members;
+    }
+    if let Some(min_keys) = changes.min_keys {
+        group.min_keys = min_keys;
+    }
+    Ok(Json(pin_and_save(&state, group).await?))
+}
+
+fn check_block_request(request: &BlockRequest) -> Result<(), &'static str>{
+    if request.reason.trim().is_empty() {
+        return Err("a reason is required.");
+    }
+    let valid = match request.kind {
+        BlockKind::User => valid_name(&request.value),
+        BlockKind::Key => request.value.starts_with("SHA256:"),
+    };
+    match valid {
+        true => Ok(()),
+        false => Err("expected a username or a 'SHA256:' key fingerprint."),
+    }
+}
+
+pub async fn list_blocklist(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<Vec<BlockEntry>>, Response>{
+    authorize(&state, &headers).map_err(IntoResponse::into_response)?;
+    let entries = with_connection(&state.database, list_blocks).await.map_err(database_error)?;
+    Ok(Json(entries))
+}
+
+pub async fn blocklist_audit(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<Vec<BlockAuditEntry>>, Response>{
+    authorize(&state, &headers).map_err(IntoResponse::into_response)?;
+    let entries = with_connection(&state.database, block_audit_trail).await.map_err(database_error)?;
+    Ok(Json(entries))
+}
+
+pub async fn block(State(state): State<AppState>, headers: HeaderMap, Json(request): Json<BlockRequest>) -> Result<(StatusCode, String), Response>{
+    authorize(&state, &headers).map_err(IntoResponse::into_response)?;
+    check_block_request(&request).map_err(|reason| error(StatusCode::UNPROCESSABLE_ENTITY, reason))?;
+    let BlockRequest{kind, value, reason} = request.clone();
+    let added = with_connection(&state.database, move |conn| add_block(conn, kind, &value, &reason, Utc::now().timestamp())).await.map_err(database_error)?;
+    Ok(match added {
+        true => (StatusCode::CREATED, format!("{} is now blocked.", request.value)),
+        false => (StatusCode::OK, format!("{} was already blocked.", request.value)),
+    })
+}
+
+pub async fn unblock(State(state): State<AppState>, headers: HeaderMap, Json(request): Json<BlockRequest>) -> Result<String, Response>{
+    authorize(&state, &headers).map_err(IntoResponse::into_response)?;
+    check_block_request(&request).map_err(|reason| error(StatusCode::UNPROCESSABLE_ENTITY, reason))?;
+    let BlockRequest{kind, value, reason} = request.clone();
+    let removed = with_connection(&state.database, move |conn| remove_block(conn, kind, &value, &reason, Utc::now().timestamp())).await.map_err(database_error)?;
+    match removed {
+        true => Ok(format!("{} is no longer blocked.", request.value)),
+        false => Err(error(StatusCode::NOT_FOUND, &format!("{} is not blocked.", request.value))),
+    }
+}
@@ -0,0 +1,51 @@
+/// Smallest group a submission may be signed by. A proof only hides the sender
+/// among the members that have a key in the group: with two members of whom
+/// one has no keys, the sender is known. Checked once the keys are fetched,
+/// before the proof is verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnonymityPolicy{
+    // distinct members with at least one key in the group
+    pub min_members: usize,
+    // distinct public keys (RSA moduli for the RSA circuit) in the group
+    pub min_keys: usize,
+}
+
+impl Default for AnonymityPolicy{
+    // a single member is always enough: the check is off unless configured
+    fn default() -> Self{
+        Self{min_members: 1, min_keys: 1}
+    }
+}
+
+impl AnonymityPolicy{
+    /// The policy for a named group: its own minimums may raise the
+    /// server-wide ones, never lower them.
+    pub fn for_group(self, min_members: Option<usize>, min_keys: Option<usize>) -> Self{
+        Self{
+            min_members: self.min_members.max(min_members.unwrap_or(0)),
+            min_keys: self.min_keys.max(min_keys.unwrap_or(0)),
+        }
+    }
+
+    /// Describes the shortfall if `members` members with `keys` keys are too few.
+    pub fn check(&self, members: usize, keys: usize) -> Result<(), String>{
+        let mut shortfalls: Vec<String> = Vec::new();
+        if members < self.min_members {
+            shortfalls.push(format!("{} with keys (at least {} required)", plural(members, "member"), self.min_members));
+        }
+        if keys < self.min_keys {
+            shortfalls.push(format!("{} (at least {} required)", plural(keys, "distinct key"), self.min_keys));
+        }
+        match shortfalls.is_empty() {
+            true => Ok(()),
+            false => Err(format!("the group is too small to keep the sender anonymous: it has {}.", shortfalls.join(" and "))),
+        }
+    }
+}
+
+fn plural(count: usize, noun: &str) -> String{
+    match count {
+        1 => format!("1 {noun}"),
+        _ => format!("{count} {noun}s"),
+    }
+}
@@ -0,0 +1,107 @@
+//! Read-only archive endpoints. `GET /` lists the archive a page at a time:
+//!
+//! ```text
+//! GET /?limit=20&order=newest&sender=alice&header=hackathon&since=2025-06-01
+//! ```
+//!
+//! The body is the list of emails; when there are more, a
+//! `Link: </?after=..>; rel="next"` header points to the next page with the
+//! same filters. `GET /search?q=hackathon` finds emails by the words of their
+//! header and message, best match first.
+
+use axum::{extract::{Json, Path, Query, State}, http::{StatusCode, header::LINK}, response::{IntoResponse, Response}};
+use serde::{Deserialize, Serialize};
+use database_lib::{EmailQuery, SearchHit, SortOrder, StoreError};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+
+use super::{blocking, AppState};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+
+// also what the next-page link is built from, so fields left out stay out
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct ArchiveParams{
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    // `YYYY-MM-DD` (midnight UTC) or RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    // substring of the header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<SortOrder>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct SearchParams{
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+fn database_error(err: StoreError) -> Response{
+    eprintln!("Database error: {err}");
+    (StatusCode::INTERNAL_SERVER_ERROR, "Sorry, could not read the archive.").into_response()
+}
+
+fn parse_date(name: &str, value: &str) -> Result<DateTime<Utc>, String>{
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|_| format!("Sorry, '{name}' must be a date like 2025-06-18 or 2025-06-18T09:30:00Z."))
+}
+
+fn email_query(params: &ArchiveParams) -> Result<EmailQuery, String>{
+    Ok(EmailQuery{
+        after: params.after,
+        limit: params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE),
+        since: params.since.as_deref().map(|value| parse_date("since", value)).transpose()?,
+        until: params.until.as_deref().map(|value| parse_date("until", value)).transpose()?,
+        recipient: params.recipient.clone(),
+        sender: params.sender.clone(),
+        header_contains: params.header.clone(),
+        order: params.order.unwrap_or_default(),
+    })
+}
+
+pub async fn list(State(state): State<AppState>, Query(params): Query<ArchiveParams>) -> Result<Response, Response>{
+    let query = email_query(&params).map_err(|reason| (StatusCode::BAD_REQUEST, reason).into_response())?;
+    let emails = state.emails.clone();
+    let page = blocking(move || emails.list(&query)).await.map_err(database_error)?;
+    Ok(match page.next {
+        Some(after) => {
+            let next = ArchiveParams{after: Some(after), ..params};
+            let link = format!("</?{}>; rel=\"next\"", serde_urlencoded::to_string(&next).unwrap_or_default());
+            ([(LINK, link)], Json(page.emails)).into_response()
+        }
+        None => Json(page.emails).into_response(),
+    })
+}
+
+// emails a member was listed as a sender of, paged like `list`
+pub async fn by_sender(state: State<AppState>, Path(username): Path<String>, Query(params): Query<ArchiveParams>) -> Result<Response, Response>{
+    list(state, Query(ArchiveParams{sender: Some(username), ..params})).await
+}
+
+pub async fn search(State(state): State<AppState>, Query(params): Query<SearchParams>) -> Result<Json<Vec<SearchHit>>, Response>{
+    if params.q.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Sorry, 'q' must name the words to search for.").into_response());
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let emails = state.emails.clone();
+    let hits = blocking(move || emails.search(&params.q, limit)).await.map_err(database_error)?;
+    Ok(Json(hits))
+}
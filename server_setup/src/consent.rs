@@ -0,0 +1,129 @@
+//! Consent registry endpoints. A GitHub user opts in (or out) by asking for a
+//! challenge and signing it with one of the RSA keys they publish:
+//!
+//! ```text
+//! POST /consent/challenge {"username": "alice", "action": "opt-in"}
+//! printf '%s' "$CHALLENGE" | ssh-keygen -Y sign -n send-group-emails-consent -f ~/.ssh/id_rsa
+//! POST /consent {"username": "alice", "action": "opt-in", "nonce": "$NONCE", "signature": "-----BEGIN SSH SIGNATURE-----..."}
+//! ```
+//!
+//! Anyone may ask for a challenge in anyone's name, so asking never cancels
+//! another open challenge, and a challenge is only used up by a valid answer.
+//!
+//! With `CONSENT_POLICY` set, senders who have not opted in are refused or
+//! left out of the group, like senders without keys.
+
+use axum::{extract::{Json, State}, http::StatusCode, response::{IntoResponse, Response}};
+use serde::{Deserialize, Serialize};
+use fetch_data_lib::{checked_username, FetchError};
+use database_lib::{open_challenge, record_opt_in, record_opt_out, store_challenge, take_challenge};
+use chrono::Utc;
+
+use super::{fetch_error_response, with_connection, AppState};
+
+// SSHSIG namespace the challenges are signed in, so the signature cannot be reused elsewhere
+pub const NAMESPACE: &str = "send-group-emails-consent";
+const CHALLENGE_TTL_SECONDS: i64 = 600;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConsentAction{
+    OptIn,
+    OptOut,
+}
+
+impl ConsentAction{
+    fn name(&self) -> &'static str{
+        match self {
+            ConsentAction::OptIn => "opt-in",
+            ConsentAction::OptOut => "opt-out",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ChallengeRequest{
+    pub username: String,
+    pub action: ConsentAction,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Challenge{
+    pub username: String,
+    pub action: ConsentAction,
+    // sent back with the answer to say which challenge it signs
+    pub nonce: String,
+    // the exact text to sign
+    pub challenge: String,
+    pub namespace: String,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SignedChallenge{
+    pub username: String,
+    pub action: ConsentAction,
+    pub nonce: String,
+    // armored output of `ssh-keygen -Y sign`
+    pub signature: String,
+}
+
+fn database_error(err: rusqlite::Error) -> Response{
+    eprintln!("Database error: {err}");
+    (StatusCode::INTERNAL_SERVER_ERROR, "Sorry, the consent registry is unavailable.").into_response()
+}
+
+fn check_username(username: &str) -> Result<(), (StatusCode, &'static str)>{
+    match checked_username(username) {
+        Ok(_) => Ok(()),
+        Err(_) => Err((StatusCode::UNPROCESSABLE_ENTITY, "Sorry, that is not a GitHub username.")),
+    }
+}
+
+pub async fn challenge(State(state): State<AppState>, Json(request): Json<ChallengeRequest>) -> Result<Json<Challenge>, Response>{
+    check_username(&request.username).map_err(IntoResponse::into_response)?;
+    let nonce: String = rand::random::<[u8; 16]>().iter().map(|byte| format!("{byte:02x}")).collect();
+    // names user and action, so a signature only answers the request it was made for
+    let challenge = format!("send_group_emails {} {} {}", request.action.name(), request.username, nonce);
+    let now = Utc::now().timestamp();
+    let expires_at = now + CHALLENGE_TTL_SECONDS;
+    let (username, action, stored, key) = (request.username.clone(), request.action, challenge.clone(), nonce.clone());
+    with_connection(&state.database, move |conn| store_challenge(conn, &username, &key, action.name(), &stored, now, expires_at)).await.map_err(database_error)?;
+    Ok(Json(Challenge{username: request.username, action: request.action, nonce, challenge, namespace: NAMESPACE.to_string(), expires_at}))
+}
+
+pub async fn answer(State(state): State<AppState>, Json(signed): Json<SignedChallenge>) -> Result<String, Response>{
+    check_username(&signed.username).map_err(IntoResponse::into_response)?;
+    let now = Utc::now().timestamp();
+    let (username, nonce, action) = (signed.username.clone(), signed.nonce.clone(), signed.action);
+    let challenge = with_connection(&state.database, move |conn| open_challenge(conn, &username, &nonce, action.name(), now)).await.map_err(database_error)?;
+    let Some(challenge) = challenge else {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "Sorry, there is no open challenge for this request; ask for a new one.").into_response());
+    };
+    let signer = match state.key_server.verify_user_signature(&signed.username, &signed.signature, NAMESPACE, challenge.as_bytes()).await {
+        Ok(signer) => signer,
+        Err(err @ FetchError::Signature{..}) => return Err((StatusCode::FORBIDDEN, format!("Sorry, could not verify the signature. \n {err}")).into_response()),
+        Err(err) => return Err(fetch_error_response(err)),
+    };
+    // used up only now that it is answered, and only once
+    let (username, nonce, fingerprint) = (signed.username.clone(), signed.nonce.clone(), signer.fingerprint());
+    let taken = with_connection(&state.database, move |conn| {
+        let tx = conn.unchecked_transaction()?;
+        if !take_challenge(&tx, &username, &nonce)? {
+            return Ok(false);
+        }
+        match action {
+            ConsentAction::OptIn => record_opt_in(&tx, &username, &fingerprint, now)?,
+            ConsentAction::OptOut => { record_opt_out(&tx, &username)?; }
+        }
+        tx.commit()?;
+        Ok(true)
+    }).await.map_err(database_error)?;
+    if !taken {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "Sorry, this challenge was already answered; ask for a new one.").into_response());
+    }
+    Ok(match signed.action {
+        ConsentAction::OptIn => format!("{} has opted in and may be named as a sender.", signed.username),
+        ConsentAction::OptOut => format!("{} has opted out and will no longer be named as a sender.", signed.username),
+    })
+}
@@ -0,0 +1,31 @@
+use lettre::Message;
+use lettre::{SmtpTransport, Transport, transport::smtp::authentication::Credentials};
+
+/// Delivers an outgoing letter. Production uses SMTP; tests capture letters
+/// in memory so the submission flow can run without a mail server.
+pub trait Mailer: Send + Sync {
+    /// Returns a short description of the server's answer on success.
+    fn send(&self, letter: &Message) -> Result<String, String>;
+}
+
+/// Sends through the Gmail SMTP relay of the kudos account.
+pub struct SmtpMailer{
+    transport: SmtpTransport,
+}
+
+impl SmtpMailer{
+    pub fn gmail(username: &str, password: &str) -> Self{
+        let creds = Credentials::new(username.into(), password.into());
+        let transport = SmtpTransport::relay("smtp.gmail.com").unwrap().credentials(creds).build();
+        Self{transport}
+    }
+}
+
+impl Mailer for SmtpMailer{
+    fn send(&self, letter: &Message) -> Result<String, String>{
+        match self.transport.send(letter){
+            Ok(response) => Ok(format!("{:?}", response)),
+            Err(e) => Err(format!("{:#?}", e)),
+        }
+    }
+}
@@ -1,101 +1,544 @@
-use axum::{Router, routing::{get, post}, extract::{State, Json}};
+use axum::{Router, routing::{get, post, put}, extract::{State, Json}, http::{StatusCode, header::RETRY_AFTER}, response::{IntoResponse, Response}};
 use serde::{Serialize, Deserialize};
-use std::{sync::{Arc, Mutex}, net::SocketAddr};
-use serde_json;
+use std::{collections::HashMap, sync::Arc, net::SocketAddr};
 use tokio::net::TcpListener;
-use lettre::message::{header, Message};
-use lettre::{SmtpTransport, Transport, transport::smtp::authentication::Credentials};
-use rusqlite::{params, Connection, Result};
-use fetch_data_lib :: {create_pb_signals};
-use verify_proof_lib :: {verify_proof};
-use database_lib::{Email, create_table, insert_email_to_database, get_email_from_database, list_all_emails_in_database};
+use lettre::message::{header, Mailbox, Message};
+use rusqlite::Connection;
+use fetch_data_lib :: {create_pb_signals_from_key_set, convert_publicSignals, apply_membership_policy, resolve_senders, MembershipPolicy, BlockedKey, Blocklist, CanonicalMessage, FetchError, GroupKeySet, GroupKeySetBuilder, KeyFamilies, KeyFamiliesBuilder, KeyServer, KeySource, KeyType};
+use database_lib::{BlockKind, ConnectionPool, Email, EmailStatus, EmailStore, Group, SqliteEmailStore, PinnedKey, consenting_members, get_group, keys_live_at, list_blocks, migrate, pinned_keys, record_seen_keys, rotation_times};
 use chrono::prelude::*;
+use chrono::format::{Item, StrftimeItems};
+
+mod admin;
+mod anonymity;
+mod archive;
+mod consent;
+mod mailer;
+mod verifier;
+#[cfg(test)]
+mod tests;
+
+use anonymity::AnonymityPolicy;
+use mailer::{Mailer, SmtpMailer};
+use verifier::{Groth16Verifier, ProofVerifier};
 
 #[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
 pub struct EmailReceived{
     pub to: Option<String>, 
     pub header: String,
     pub message: String,
+    #[serde(default)]
     pub senders: Vec<String>,
-    pub group_signature: String
+    pub group_signature: String,
+    // optional UNIX timestamp chosen by the signer, covered by the signature
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    // family of the key the proof was made with; selects circuit and verifying key
+    #[serde(default)]
+    pub key_type: KeyType,
+    // name of a server-managed group, instead of `senders`
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+// e.g. `Wed, 18 Jun 2025 09:30:00 UTC`
+const DEFAULT_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S UTC";
+
+#[derive(Clone)]
+struct AppState{
+    // groups, consent, blocklist and key history
+    database: ConnectionPool,
+    // the archive of accepted emails
+    emails: Arc<dyn EmailStore>,
+    // what to do with senders that have no usable key of the submission's family
+    membership_policy: MembershipPolicy,
+    // fewest members and keys a group needs to hide the sender; groups may ask for more
+    anonymity: AnonymityPolicy,
+    // where members' public keys are downloaded from
+    key_server: KeyServer,
+    // one verifying key per key family the server accepts
+    verifiers: HashMap<KeyType, Arc<dyn ProofVerifier>>,
+    // how far back key snapshots are tried when the current keys do not verify; 0 turns it off
+    key_grace_hours: i64,
+    // how far a signed timestamp may be from the server's clock, either way; 0 turns the check off
+    timestamp_window_minutes: i64,
+    // strftime pattern and locale dates are shown with in letters and notes; stored and served dates are UTC
+    date_format: String,
+    date_locale: Locale,
+    // bearer token of the admin endpoints; they are disabled without one
+    admin_token: Option<String>,
+    // whether senders must have opted in (see `consent`), and what happens to those who have not
+    consent_policy: Option<MembershipPolicy>,
+    mailer: Arc<dyn Mailer>,
 }
 
-type EmailDatabase = Arc<Mutex<Connection>>;
-// show all emails in database
-//todo: add better error handling
-async fn send_list_emails(State(database_conn): State<EmailDatabase>) -> Json<Vec<Email>> {
-    let data = list_all_emails_in_database(&database_conn.lock().unwrap()).unwrap(); // Access the vector
-    Json(data.clone())   
+impl AppState{
+    // the one place dates are turned into text for people
+    fn format_date(&self, date: DateTime<Utc>) -> String{
+        date.format_localized(&self.date_format, self.date_locale).to_string()
+    }
 }
 
 async fn create_the_message(list_senders: Vec<String>, message : String) -> String{
-    let mut result :String = "".to_string();
-    result = message + "\nBest, \nParticipant of a group : \n";
+    let mut result :String = message + "\nBest, \nParticipant of a group : \n";
     for sender in list_senders{
         result = result + &sender + "\n";
     }    result 
 }
 
-async fn receive_email(State(database_conn): State<EmailDatabase>, Json(email): Json<EmailReceived>) -> String{
-    let date: String= Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
-    
-    let email_database = Email{to: email.to.clone(), header: email.header.clone(), message: email.message.clone(), senders: email.senders.clone(), group_signature: email.group_signature.clone(), date: date.clone()};
+// maps key fetching and group building failures to a status the client can act on
+fn fetch_error_response(err: FetchError) -> Response{
+    let status = match &err {
+        FetchError::UserNotFound{..} | FetchError::GroupNotFound{..} => StatusCode::NOT_FOUND,
+        FetchError::RateLimited{..} => StatusCode::SERVICE_UNAVAILABLE,
+        FetchError::Http{..} | FetchError::Network{..} => StatusCode::BAD_GATEWAY,
+        FetchError::MalformedKey{..} | FetchError::NoMembers | FetchError::NoUsableKeys{..} | FetchError::MembershipTooLarge{..}
+        | FetchError::GroupTooLarge{..} | FetchError::IneligibleSenders{..} | FetchError::InvalidInput{..} => StatusCode::UNPROCESSABLE_ENTITY,
+        FetchError::BlockedSenders{..} => StatusCode::FORBIDDEN,
+        FetchError::Signature{..} | FetchError::Agent{..} => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let body = format!("Sorry, could not process your request. \n {err}");
+    match err {
+        FetchError::RateLimited{retry_after: Some(seconds), ..} => (status, [(RETRY_AFTER, seconds.to_string())], body).into_response(),
+        _ => (status, body).into_response(),
+    }
+}
 
-    let to_addr  = email.to.clone().unwrap_or_else(|| "sansome-talk@0xparc.org".into());
+// transient key server failures (network, 5xx) are retried once; rate limits are passed on.
+// keys left out of the group are logged, the rest of the group still counts
+async fn fetch_key_families(key_server: &KeyServer, senders: &[String]) -> Result<KeyFamilies, FetchError>{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match key_server.fetch_key_families(senders.to_vec()).await {
+            Err(err) if attempts < 2 && err.is_retryable() && !matches!(err, FetchError::RateLimited{..}) => continue,
+            Ok(families) => {
+                for err in families.skipped() {
+                    eprintln!("Skipped key: {err}");
+                }
+                return Ok(families);
+            }
+            result => return result,
+        }
+    }
+}
+
+// every key of every family, once per member publishing it
+fn family_keys(families: &KeyFamilies) -> Vec<PinnedKey>{
+    let mut result: Vec<PinnedKey> = Vec::new();
+    for key_type in families.key_types() {
+        for key in families.get(key_type).into_iter().flat_map(|set| set.keys()) {
+            for username in &key.usernames {
+                result.push(PinnedKey{username: username.clone(), key_type: key_type.name().to_string(), public_key: key.public_key.clone()});
+            }
+        }
+    }
+    result
+}
+
+// remembers every fetched key so proofs made just before a rotation can still be checked
+fn record_key_history(conn: &Connection, families: &KeyFamilies, seen_at: i64) -> rusqlite::Result<()>{
+    let mut keys: HashMap<String, Vec<(String, Vec<u8>)>> = HashMap::new();
+    for key in family_keys(families) {
+        keys.entry(key.username).or_default().push((key.key_type, key.public_key));
+    }
+    for (username, keys) in keys {
+        record_seen_keys(conn, &username, &keys, seen_at)?;
+    }
+    Ok(())
+}
+
+// key sets the senders had just before each rotation in [since, before), newest first;
+// sets equal to `current` are skipped
+fn key_set_snapshots(conn: &Connection, senders: &[String], key_type: KeyType, blocklist: &Blocklist, current: &GroupKeySet, since: i64, before: i64) -> rusqlite::Result<Vec<(i64, GroupKeySet)>>{
+    let mut result: Vec<(i64, GroupKeySet)> = Vec::new();
+    for at in rotation_times(conn, senders, since, before)? {
+        let mut builder = GroupKeySetBuilder::new().key_type(key_type);
+        for sender in senders {
+            builder.add_member(sender);
+        }
+        for record in keys_live_at(conn, senders, at)? {
+            if record.key_type == key_type.name() && !blocklist.is_key_blocked(key_type, &record.public_key) {
+                // the history matches usernames case-insensitively, the key set by the sender's spelling
+                let username = senders.iter().find(|sender| sender.eq_ignore_ascii_case(&record.username)).unwrap_or(&record.username);
+                builder.add_key(username, &record.public_key);
+            }
+        }
+        if let Ok(key_set) = builder.build()
+            && key_set.hash() != current.hash()
+            && result.iter().all(|(_, known)| known.hash() != key_set.hash())
+        {
+            result.push((at, key_set));
+        }
+    }
+    Ok(result)
+}
+
+// the keys a group pinned when it was last saved, for those of its members taking part
+fn pinned_families(members: &[String], keys: &[PinnedKey]) -> Result<KeyFamilies, FetchError>{
+    let mut builder = KeyFamiliesBuilder::new();
+    for member in members {
+        builder.add_member(member);
+    }
+    for key in keys.iter().filter(|key| members.contains(&key.username)) {
+        builder.add_key(&key.username, key.key_type.parse()?, &key.public_key);
+    }
+    builder.build()
+}
+
+fn load_blocklist(conn: &Connection) -> rusqlite::Result<Blocklist>{
+    let mut blocklist = Blocklist::new();
+    for entry in list_blocks(conn)? {
+        match entry.kind {
+            BlockKind::User => blocklist.block_user(&entry.value),
+            BlockKind::Key => blocklist.block_key(&entry.value),
+        };
+    }
+    Ok(blocklist)
+}
+
+fn load_group(conn: &Connection, name: &str) -> rusqlite::Result<Option<(Group, Vec<PinnedKey>)>>{
+    match get_group(conn, name)? {
+        Some(group) => Ok(Some((group, pinned_keys(conn, name)?))),
+        None => Ok(None),
+    }
+}
+
+// runs `work` on the blocking thread pool, so database calls never stall the async runtime
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T{
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => result,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+// runs `work` with a pooled connection on the blocking thread pool
+async fn with_connection<T: Send + 'static>(pool: &ConnectionPool, work: impl FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static) -> rusqlite::Result<T>{
+    let pool = pool.clone();
+    blocking(move || work(&*pool.get()?)).await
+}
+
+async fn public_signals_json(key_set: &GroupKeySet, message: &CanonicalMessage) -> Result<String, FetchError>{
+    let pb_signals = convert_publicSignals(create_pb_signals_from_key_set(key_set, message)?).await;
+    serde_json::to_string(&pb_signals).map_err(|err| FetchError::InvalidInput{reason: err.to_string()})
+}
+
+async fn receive_email(State(state): State<AppState>, Json(email): Json<EmailReceived>) -> Result<String, Response>{
+    let now = Utc::now();
+
+    // a signed timestamp far from now is a replayed or back-dated proof
+    if let Some(timestamp) = email.timestamp
+        && state.timestamp_window_minutes > 0
+        && !i64::try_from(timestamp).is_ok_and(|timestamp| (timestamp - now.timestamp()).abs() <= state.timestamp_window_minutes * 60)
+    {
+        let reason = format!("Sorry, the signed timestamp is more than {} minutes away from the server's clock.", state.timestamp_window_minutes);
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, reason).into_response());
+    }
+
+    // a named group brings its members, its pinned keys and a default recipient
+    let group = match &email.group {
+        Some(_) if !email.senders.is_empty() => {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, "Sorry, a submission names either senders or a group, not both.").into_response());
+        }
+        Some(name) => match with_connection(&state.database, { let name = name.clone(); move |conn| load_group(conn, &name) }).await {
+            Ok(Some(group)) => Some(group),
+            Ok(None) => return Err((StatusCode::NOT_FOUND, format!("Sorry, there is no group '{name}'.")).into_response()),
+            Err(err) => {
+                eprintln!("Database error: {err}");
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, "Sorry, could not load the group.").into_response());
+            }
+        },
+        None => None,
+    };
+    // the group's default recipient is part of what its members sign
+    let to = email.to.clone().or_else(|| group.as_ref().and_then(|(group, _)| group.recipient.clone()));
+    let to_addr  = to.clone().unwrap_or_else(|| "sansome-talk@0xparc.org".into());
+    // checked before anything is archived, a bad address would otherwise only fail once the letter is built
+    let to_mailbox: Mailbox = match to_addr.parse() {
+        Ok(mailbox) => mailbox,
+        Err(_) => return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Sorry, '{to_addr}' is not a valid recipient address.")).into_response()),
+    };
     let subject   = email.header.clone();      // or borrow &email.header
-    let pb_signals = match create_pb_signals(email.senders.clone(), &email.message.clone()).await {
-        Ok(body)  => body,
-        Err(err) => return format!("Sorry, could not process your request. \n {err}"),
+    let verifier = match state.verifiers.get(&email.key_type) {
+        Some(verifier) => verifier.clone(),
+        None => return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Sorry, this server does not accept proofs for {} keys.", email.key_type)).into_response()),
+    };
+    // `org:` and `team:` entries stand for their GitHub members
+    let members = match &group {
+        Some((group, _)) => group.members.clone(),
+        None => resolve_senders(&state.key_server, &email.senders).await.map_err(fetch_error_response)?,
+    };
+    // members who never opted in are either refused or left out of the group
+    let mut not_consenting: Vec<String> = Vec::new();
+    let senders = match state.consent_policy {
+        None => members,
+        Some(policy) => {
+            let consenting = with_connection(&state.database, { let members = members.clone(); move |conn| consenting_members(conn, &members) }).await.map_err(|err| {
+                eprintln!("Database error: {err}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Sorry, the consent registry is unavailable.").into_response()
+            })?;
+            not_consenting = members.into_iter().filter(|member| !consenting.contains(member)).collect();
+            if policy == MembershipPolicy::Reject && !not_consenting.is_empty() {
+                let reason = format!("Sorry, the following senders have not opted in to being named: {}", not_consenting.join(", "));
+                return Err((StatusCode::UNPROCESSABLE_ENTITY, reason).into_response());
+            }
+            consenting
+        }
+    };
+    let blocklist = with_connection(&state.database, load_blocklist).await.map_err(|err| {
+        eprintln!("Database error: {err}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Sorry, the blocklist is unavailable.").into_response()
+    })?;
+    blocklist.check_senders(&senders).map_err(fetch_error_response)?;
+    // fetched keys are remembered once a proof verifies against them, see `record_key_history`
+    let (families, fetched) = match &group {
+        Some((_, keys)) => (pinned_families(&senders, keys).map_err(fetch_error_response)?, None),
+        None => {
+            let families = fetch_key_families(&state.key_server, &senders).await.map_err(fetch_error_response)?;
+            (families.clone(), Some(families))
+        }
+    };
+    // blocked keys never reach the public signals
+    let (families, blocked_keys) = blocklist.filter_families(families).map_err(fetch_error_response)?;
+    let blocked_keys: Vec<BlockedKey> = blocked_keys.into_iter().filter(|key| key.key_type == email.key_type).collect();
+    // members may publish keys of several families; only the submission's family forms the group
+    let key_set = families.into_key_set(email.key_type).map_err(fetch_error_response)?;
+    if let Some((group, _)) = &group
+        && key_set.len() > group.max_keys
+    {
+        return Err(fetch_error_response(FetchError::GroupTooLarge{keys: key_set.len(), max: group.max_keys}));
+    }
+    // senders without keys could not have signed, so they are either refused or not displayed
+    let mut membership = apply_membership_policy(&senders, &key_set, state.membership_policy).map_err(fetch_error_response)?;
+    let anonymity = match &group {
+        Some((group, _)) => state.anonymity.for_group(group.min_members, group.min_keys),
+        None => state.anonymity,
     };
-    let mut text = create_the_message(email.senders.clone(), email.message.clone()).await;
-    let input_pb_signals = match serde_json::to_string(&pb_signals){
-        Ok(body) => body,
-        Err(err) => return format!("Error processing public signals. Check the input fomating."),
+    if let Err(shortfall) = anonymity.check(membership.eligible.len(), key_set.len()) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Sorry, {shortfall}")).into_response());
+    }
+    // the proof covers recipient, header, body and timestamp, not just the body
+    let signed_message = CanonicalMessage::new(to.clone(), &email.header, &email.message, email.timestamp);
+    let input_pb_signals = public_signals_json(&key_set, &signed_message).await.map_err(fetch_error_response)?;
+    let mut flag = verifier.verify(&email.group_signature, &input_pb_signals).await;
+
+    // a member may have rotated keys after the proof was made: retry with recent snapshots
+    // (pinned group keys do not change until the group is saved again)
+    let mut snapshot: Option<i64> = None;
+    if matches!(flag, Ok(false)) && state.key_grace_hours > 0 && group.is_none() {
+        let since = now.timestamp() - state.key_grace_hours * 3600;
+        let (members, key_type, blocklist, current) = (senders.clone(), email.key_type, blocklist.clone(), key_set.clone());
+        let snapshots = with_connection(&state.database, move |conn| key_set_snapshots(conn, &members, key_type, &blocklist, &current, since, now.timestamp())).await.map_err(|err| {
+            eprintln!("Database error: {err}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Sorry, could not load the key history.").into_response()
+        })?;
+        for (at, old_key_set) in snapshots {
+            let Ok(old_membership) = apply_membership_policy(&senders, &old_key_set, state.membership_policy) else { continue };
+            if anonymity.check(old_membership.eligible.len(), old_key_set.len()).is_err() {
+                continue;
+            }
+            let Ok(old_pb_signals) = public_signals_json(&old_key_set, &signed_message).await else { continue };
+            if let Ok(true) = verifier.verify(&email.group_signature, &old_pb_signals).await {
+                flag = Ok(true);
+                membership = old_membership;
+                snapshot = Some(at);
+                break;
+            }
+        }
+    }
+    // failed submissions leave no history, so they cannot stretch what the grace period accepts
+    if let (Ok(true), Some(seen)) = (&flag, fetched)
+        && let Err(err) = with_connection(&state.database, move |conn| record_key_history(conn, &seen, now.timestamp())).await
+    {
+        eprintln!("Failed to record key history: {err}");
+    }
+
+    let mut warning = match membership.warning() {
+        Some(warning) => format!("\n Warning: {warning}"),
+        None => String::new(),
     };
-    let flag = verify_proof(&email.group_signature, &input_pb_signals,  &"../verification_key.json".to_string()).await;
-   
+    let blocked_note = match blocked_keys.is_empty() {
+        true => String::new(),
+        false => {
+            let keys: Vec<String> = blocked_keys.iter().map(|key| format!("{} ({})", key.fingerprint, key.usernames.join(", "))).collect();
+            format!("\n Note: blocked keys were left out of the group, proofs made with them are not accepted: {}", keys.join("; "))
+        }
+    };
+    warning += &blocked_note;
+    if !not_consenting.is_empty() {
+        warning += &format!("\n Warning: left out because they have not opted in to being named: {}", not_consenting.join(", "));
+    }
+    if let Some(at) = snapshot.and_then(|at| DateTime::<Utc>::from_timestamp(at, 0)) {
+        warning += &format!("\n Note: the proof was verified against the group's keys as of {}; members have rotated keys since.", state.format_date(at));
+    }
+    let email_database = Email{to: to.clone(), header: email.header.clone(), message: email.message.clone(), senders: membership.eligible.clone(), group_signature: email.group_signature.clone(), date: now, members: senders.clone(), status: EmailStatus::Pending, timestamp: email.timestamp};
+    let text = create_the_message(membership.eligible.clone(), email.message.clone()).await;
+
     match flag {
-        Ok(body) => { 
+        Ok(body) => {
             if body {
-                let email_id = insert_email_to_database(&database_conn.lock().unwrap(), &email_database).unwrap();
-                
+                let emails = state.emails.clone();
+                let email_id = blocking(move || emails.insert(&email_database)).await.map_err(|err| {
+                    eprintln!("Database error: {err}");
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Sorry, could not archive the email.").into_response()
+                })?;
                 let letter = Message::builder()
                                     .from("kudos@0xparc.org".parse().unwrap())
-                                    .to(to_addr.parse().unwrap())
+                                    .to(to_mailbox)
                                     .subject(subject)
                                     .header(header::ContentType::TEXT_PLAIN)
-                                    .body(text + &format!("\n \n Date: {} \n Email id: {} \n \n Group Signature: {} \n (Trust us bro)", date, email_id, &email.group_signature))
+                                    .body(text + &format!("\n \n Date: {} \n Email id: {} \n \n Group Signature: {} \n (Trust us bro)", state.format_date(now), email_id, &email.group_signature))
                                     .unwrap();
-                let creds = Credentials::new("kudos@0xparc.org".into(), "szmi aljp ugko evld".into());
-                let mailer = SmtpTransport::relay("smtp.gmail.com").unwrap().credentials(creds).build();
-                match mailer.send(&letter){
-                    Ok(response) => {
-                        return format!("Email sent! Server said: {:?}", response); },
-                    Err(e) => {
-                        return format!("Failed to send email: {:#?}", e);
-                    }
-                };
+                // SMTP is a blocking round trip to the mail server
+                let mailer = state.mailer.clone();
+                let sent = blocking(move || mailer.send(&letter)).await;
+                let status = if sent.is_ok() { EmailStatus::Sent } else { EmailStatus::Failed };
+                let emails = state.emails.clone();
+                if let Err(err) = blocking(move || emails.set_status(email_id, status)).await {
+                    eprintln!("Failed to record delivery of email {email_id}: {err}");
+                }
+                match sent{
+                    Ok(response) => Ok(format!("Email sent! Server said: {}{}", response, warning)),
+                    // the mail server is the upstream that failed
+                    Err(e) => Err((StatusCode::BAD_GATEWAY, format!("Failed to send email: {}", e)).into_response()),
+                }
             } else {
-                return format!("Sorry, signature is incorrect");
+                Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Sorry, signature is incorrect{blocked_note}")).into_response())
             }
         },
-        Err(err) => return format!("Sorry, could not verify proof due to the {err}."),
-    };
-    return "End of the function".to_string();
+        // almost always a malformed proof in the submission
+        Err(err) => Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Sorry, could not verify proof due to the {err}.")).into_response()),
+    }
+}
+
+fn app(state: AppState) -> Router{
+    Router::new()
+        .route("/", get(archive::list).post(receive_email))
+        .route("/senders/{username}", get(archive::by_sender))
+        .route("/search", get(archive::search))
+        .route("/consent/challenge", post(consent::challenge))
+        .route("/consent", post(consent::answer))
+        .route("/admin/blocklist", get(admin::list_blocklist).post(admin::block).delete(admin::unblock))
+        .route("/admin/blocklist/audit", get(admin::blocklist_audit))
+        .route("/admin/groups", get(admin::list).post(admin::create))
+        .route("/admin/groups/{name}", put(admin::edit))
+        .with_state(state)
 }
 
 #[tokio::main]
 async fn main() {
-    let database: EmailDatabase =  Arc::new(Mutex::new(Connection::open("emails.db").expect("Failed to open database")));
-    create_table(&database.lock().unwrap()).expect("Failed to create table");
-    
-    let router = Router::new()
-                    .route("/", get(send_list_emails))
-                    .route("/", post(receive_email))
-                    .with_state(database);
+    let pool_size: usize = match std::env::var("DATABASE_POOL_SIZE") {
+        Ok(value) => value.parse().expect("Invalid DATABASE_POOL_SIZE"),
+        Err(_) => 8,
+    };
+    let database = ConnectionPool::open("emails.db", pool_size).expect("Failed to open database");
+    // refuses to start against a database written by a newer version
+    migrate(&database.get().expect("Failed to open database")).expect("Failed to migrate database");
+    let membership_policy = match std::env::var("MEMBERSHIP_POLICY") {
+        Ok(value) => value.parse().expect("Invalid MEMBERSHIP_POLICY"),
+        Err(_) => MembershipPolicy::default(),
+    };
+    let key_server = match std::env::var("KEY_SERVER_URL") {
+        Ok(value) => KeyServer::new(&value),
+        Err(_) => KeyServer::default(),
+    };
+    let key_server = match std::env::var("GITHUB_API_URL") {
+        Ok(value) => key_server.with_api_url(&value),
+        Err(_) => key_server,
+    };
+    // a token raises the REST API rate limit; only sent to the API
+    let key_server = match std::env::var("GITHUB_TOKEN") {
+        Ok(value) => key_server.with_token(&value),
+        Err(_) => key_server,
+    };
+    let key_server = match std::env::var("KEY_SERVER_PROXY") {
+        Ok(value) => key_server.with_proxy(&value).expect("Invalid KEY_SERVER_PROXY"),
+        Err(_) => key_server,
+    };
+    // comma separated, e.g. KEY_SOURCES=api,gpg
+    let key_server = match std::env::var("KEY_SOURCES") {
+        Ok(value) => {
+            let sources: Vec<KeySource> = value.split(',').map(|source| source.trim().parse().expect("Invalid KEY_SOURCES")).collect();
+            key_server.with_sources(&sources)
+        }
+        Err(_) => key_server,
+    };
+    let verification_key_path = std::env::var("VERIFICATION_KEY_PATH").unwrap_or_else(|_| "../verification_key.json".to_string());
+    // off by default: within the grace period a key the member removed, for
+    // instance because it leaked, still verifies proofs. Only set this when
+    // proofs are routinely submitted a while after they are made.
+    let key_grace_hours: i64 = match std::env::var("KEY_GRACE_HOURS") {
+        Ok(value) => value.parse().expect("Invalid KEY_GRACE_HOURS"),
+        Err(_) => 0,
+    };
+    let timestamp_window_minutes: i64 = match std::env::var("TIMESTAMP_WINDOW_MINUTES") {
+        Ok(value) => value.parse().expect("Invalid TIMESTAMP_WINDOW_MINUTES"),
+        Err(_) => 24 * 60,
+    };
+    // unset or `off`: anyone may be named; `drop` or `reject`: only members who opted in
+    let consent_policy: Option<MembershipPolicy> = match std::env::var("CONSENT_POLICY") {
+        Ok(value) if value.trim().eq_ignore_ascii_case("off") => None,
+        Ok(value) => Some(value.parse().expect("Invalid CONSENT_POLICY")),
+        Err(_) => None,
+    };
+    // e.g. MIN_ANONYMITY_MEMBERS=3 refuses groups where fewer than 3 members have keys
+    let mut anonymity = AnonymityPolicy::default();
+    if let Ok(value) = std::env::var("MIN_ANONYMITY_MEMBERS") {
+        anonymity.min_members = value.parse().expect("Invalid MIN_ANONYMITY_MEMBERS");
+    }
+    if let Ok(value) = std::env::var("MIN_ANONYMITY_KEYS") {
+        anonymity.min_keys = value.parse().expect("Invalid MIN_ANONYMITY_KEYS");
+    }
+    let date_format = std::env::var("DATE_FORMAT").unwrap_or_else(|_| DEFAULT_DATE_FORMAT.to_string());
+    // an invalid pattern would only fail when the first letter is formatted
+    if StrftimeItems::new(&date_format).any(|item| item == Item::Error) {
+        panic!("Invalid DATE_FORMAT");
+    }
+    // e.g. DATE_LOCALE=de_DE names days and months in German
+    let date_locale: Locale = match std::env::var("DATE_LOCALE") {
+        Ok(value) => match value.parse() {
+            Ok(locale) => locale,
+            Err(_) => {
+                eprintln!("Invalid DATE_LOCALE '{value}', expected a locale such as en_US or de_DE");
+                std::process::exit(1);
+            }
+        },
+        Err(_) => Locale::en_US,
+    };
+    // the Gmail account letters are sent from, with an app password
+    let smtp_username = std::env::var("SMTP_USERNAME").unwrap_or_else(|_| "kudos@0xparc.org".to_string());
+    let smtp_password = std::env::var("SMTP_PASSWORD").expect("SMTP_PASSWORD must be set");
+    let mut verifiers: HashMap<KeyType, Arc<dyn ProofVerifier>> = HashMap::new();
+    verifiers.insert(KeyType::Rsa, Arc::new(Groth16Verifier{verification_key_path}));
+    // other families are only accepted once their verifying key is configured, and only in
+    // builds with `experimental-key-families`: no circuit in this tree checks their layouts
+    for (key_type, variable) in [(KeyType::Ed25519, "VERIFICATION_KEY_PATH_ED25519"), (KeyType::EcdsaP256, "VERIFICATION_KEY_PATH_P256")] {
+        if let Ok(verification_key_path) = std::env::var(variable) {
+            if !cfg!(feature = "experimental-key-families") {
+                panic!("{variable} is set, but {key_type} proofs need a build with the experimental-key-families feature");
+            }
+            eprintln!("Warning: accepting experimental {key_type} proofs; their public-signal layout is provisional.");
+            verifiers.insert(key_type, Arc::new(Groth16Verifier{verification_key_path}));
+        }
+    }
+    let state = AppState{
+        emails: Arc::new(SqliteEmailStore::new(database.clone())),
+        database,
+        membership_policy,
+        anonymity,
+        key_server,
+        verifiers,
+        key_grace_hours,
+        timestamp_window_minutes,
+        date_format,
+        date_locale,
+        admin_token: std::env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty()),
+        consent_policy,
+        mailer: Arc::new(SmtpMailer::gmail(&smtp_username, &smtp_password)),
+    };
 
     let addr = SocketAddr::from(([127,0,0,1], 8000));
     let tcp = TcpListener::bind(&addr).await.unwrap();
 
-    axum::serve(tcp, router).await.unwrap();
+    axum::serve(tcp, app(state)).await.unwrap();
 }
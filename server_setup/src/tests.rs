@@ -0,0 +1,705 @@
+//! End-to-end tests of the submission flow. Keys come from a local stand-in
+//! for github.com serving `fixtures/keys/<user>.keys`, letters are captured
+//! in memory and the database lives in memory.
+
+use super::*;
+use axum::http::{Uri, header::LINK};
+use std::{path::PathBuf, sync::{Mutex, atomic::{AtomicBool, Ordering}}};
+use fetch_data_lib::MAX_GROUP_SIZE;
+use verifier::VerifyFuture;
+use database_lib::{BlockAuditEntry, EmailQuery, InMemoryEmailStore, SearchHit};
+use fetch_data_lib::{load_private_key_pem, parse_public_keys, sign_ssh_signature};
+use ark_bn254::{Bn254, Fq, Fr, G1Affine, G2Affine};
+use ark_ff::PrimeField;
+use ark_groth16::Groth16;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_snark::SNARK;
+use rand::SeedableRng;
+
+fn fixture_path(relative: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(relative)
+}
+
+/// Keeps every letter instead of sending it, or refuses them all once `refuse` is set.
+#[derive(Default)]
+struct CaptureMailer {
+    letters: Mutex<Vec<String>>,
+    refuse: AtomicBool,
+}
+
+impl Mailer for CaptureMailer {
+    fn send(&self, letter: &Message) -> Result<String, String> {
+        if self.refuse.load(Ordering::SeqCst) {
+            return Err("connection refused".to_string());
+        }
+        self.letters.lock().unwrap().push(String::from_utf8_lossy(&letter.formatted()).into_owned());
+        Ok("captured".to_string())
+    }
+}
+
+/// Accepts every proof and remembers the public signals it was asked about.
+#[derive(Default)]
+struct RecordingVerifier {
+    public_signals: Mutex<Vec<String>>,
+}
+
+impl ProofVerifier for RecordingVerifier {
+    fn verify<'a>(&'a self, _proof: &'a str, public_signals: &'a str) -> VerifyFuture<'a> {
+        self.public_signals.lock().unwrap().push(public_signals.to_string());
+        Box::pin(async { Ok(true) })
+    }
+}
+
+/// Accepts only proofs over the given public signals.
+struct ExpectingVerifier {
+    public_signals: String,
+}
+
+impl ProofVerifier for ExpectingVerifier {
+    fn verify<'a>(&'a self, _proof: &'a str, public_signals: &'a str) -> VerifyFuture<'a> {
+        let accepted = public_signals == self.public_signals;
+        Box::pin(async move { Ok(accepted) })
+    }
+}
+
+/// A circuit whose only constraints are its public inputs. Proofs made with it
+/// are real Groth16 proofs, over exactly the signals they were made for; the
+/// group signature circuit itself cannot be proven without snarkjs.
+struct PublicInputsCircuit {
+    public_signals: Vec<Fr>,
+}
+
+impl ConstraintSynthesizer<Fr> for PublicInputsCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+        for signal in self.public_signals {
+            cs.new_input_variable(|| Ok(signal))?;
+        }
+        Ok(())
+    }
+}
+
+/// Proves `public_signals` (the JSON array the server verifies against) and
+/// returns the proof and the verifying key in snarkjs' JSON layout.
+fn groth16_fixture(public_signals: &[String]) -> (String, String) {
+    let public_signals: Vec<Fr> = public_signals.iter().map(|signal| signal.parse().unwrap()).collect();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let circuit = || PublicInputsCircuit { public_signals: public_signals.clone() };
+    let (proving_key, verifying_key) = Groth16::<Bn254>::circuit_specific_setup(circuit(), &mut rng).unwrap();
+    let proof = Groth16::<Bn254>::prove(&proving_key, circuit(), &mut rng).unwrap();
+
+    let decimal = |value: &Fq| value.into_bigint().to_string();
+    let g1 = |point: &G1Affine| serde_json::json!([decimal(&point.x), decimal(&point.y), "1"]);
+    let g2 = |point: &G2Affine| {
+        serde_json::json!([
+            [decimal(&point.x.c0), decimal(&point.x.c1)],
+            [decimal(&point.y.c0), decimal(&point.y.c1)],
+            ["1", "0"]
+        ])
+    };
+    let proof = serde_json::json!({
+        "pi_a": g1(&proof.a), "pi_b": g2(&proof.b), "pi_c": g1(&proof.c), "protocol": "groth16", "curve": "bn128",
+    });
+    let verifying_key = serde_json::json!({
+        "protocol": "groth16",
+        "curve": "bn128",
+        "nPublic": public_signals.len(),
+        "vk_alpha_1": g1(&verifying_key.alpha_g1),
+        "vk_beta_2": g2(&verifying_key.beta_g2),
+        "vk_gamma_2": g2(&verifying_key.gamma_g2),
+        "vk_delta_2": g2(&verifying_key.delta_g2),
+        "IC": verifying_key.gamma_abc_g1.iter().map(g1).collect::<Vec<_>>(),
+    });
+    (proof.to_string(), verifying_key.to_string())
+}
+
+async fn serve(router: Router) -> String {
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = tcp.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(tcp, router).await.unwrap() });
+    format!("http://{}/", addr)
+}
+
+/// Serves `fixtures/keys/<user>.keys` the way github.com serves `<user>.keys`,
+/// and the members of the organization `parc` and its team `core` the way
+/// api.github.com does.
+async fn start_key_server() -> KeyServer {
+    async fn keys(uri: Uri) -> Response {
+        let name = uri.path().trim_start_matches('/');
+        match name {
+            "orgs/parc/members" => return (StatusCode::OK, r#"[{"login":"alice"},{"login":"bob"},{"login":"carol"}]"#).into_response(),
+            "orgs/parc/teams/core/members" => return (StatusCode::OK, r#"[{"login":"bob"},{"login":"alice"}]"#).into_response(),
+            _ => {}
+        }
+        if name == "throttled.keys" {
+            return (StatusCode::TOO_MANY_REQUESTS, [(RETRY_AFTER, "60")], "slow down").into_response();
+        }
+        match std::fs::read_to_string(fixture_path("fixtures/keys").join(name)) {
+            Ok(body) if name.ends_with(".keys") => (StatusCode::OK, body).into_response(),
+            _ => (StatusCode::NOT_FOUND, "Not Found").into_response(),
+        }
+    }
+    let url = serve(Router::new().fallback(keys)).await;
+    KeyServer::new(&url).with_api_url(&url)
+}
+
+const ADMIN_TOKEN: &str = "admin-secret";
+
+struct TestServer {
+    url: String,
+    key_server: KeyServer,
+    mailer: Arc<CaptureMailer>,
+    database: ConnectionPool,
+    emails: Arc<dyn EmailStore>,
+}
+
+async fn start_server(membership_policy: MembershipPolicy, verifier: Arc<dyn ProofVerifier>) -> TestServer {
+    start_server_with(membership_policy, HashMap::from([(KeyType::Rsa, verifier)]), None).await
+}
+
+async fn start_server_with(
+    membership_policy: MembershipPolicy,
+    verifiers: HashMap<KeyType, Arc<dyn ProofVerifier>>,
+    consent_policy: Option<MembershipPolicy>,
+) -> TestServer {
+    // the archive lives in memory; the other tables in an in-memory database
+    launch(membership_policy, verifiers, consent_policy, |_| Arc::new(InMemoryEmailStore::new())).await
+}
+
+/// Like `start_server`, with the archive in the pooled database, as in production.
+async fn start_server_on_sqlite(membership_policy: MembershipPolicy, verifier: Arc<dyn ProofVerifier>) -> TestServer {
+    let verifiers = HashMap::from([(KeyType::Rsa, verifier)]);
+    launch(membership_policy, verifiers, None, |database| Arc::new(SqliteEmailStore::new(database.clone()))).await
+}
+
+async fn launch(
+    membership_policy: MembershipPolicy,
+    verifiers: HashMap<KeyType, Arc<dyn ProofVerifier>>,
+    consent_policy: Option<MembershipPolicy>,
+    emails: impl FnOnce(&ConnectionPool) -> Arc<dyn EmailStore>,
+) -> TestServer {
+    let database = ConnectionPool::in_memory().unwrap();
+    migrate(&database.get().unwrap()).unwrap();
+    let key_server = start_key_server().await;
+    let mailer = Arc::new(CaptureMailer::default());
+    let emails = emails(&database);
+    let state = AppState {
+        database: database.clone(),
+        emails: emails.clone(),
+        membership_policy,
+        anonymity: AnonymityPolicy::default(),
+        key_server: key_server.clone(),
+        verifiers,
+        // opted in, so the rotation test can exercise it
+        key_grace_hours: 24,
+        timestamp_window_minutes: 60,
+        date_format: DEFAULT_DATE_FORMAT.to_string(),
+        date_locale: Locale::en_US,
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        consent_policy,
+        mailer: mailer.clone(),
+    };
+    let url = serve(app(state)).await;
+    TestServer { url, key_server, mailer, database, emails }
+}
+
+fn submission(senders: &[&str], group_signature: &str) -> EmailReceived {
+    EmailReceived {
+        to: Some("team@example.org".to_string()),
+        header: "Kudos".to_string(),
+        message: "Thanks for the demo!".to_string(),
+        senders: senders.iter().map(|sender| sender.to_string()).collect(),
+        group_signature: group_signature.to_string(),
+        timestamp: Some(Utc::now().timestamp() as u64),
+        key_type: KeyType::Rsa,
+        group: None,
+    }
+}
+
+async fn post(server: &TestServer, email: &EmailReceived) -> String {
+    send(server, email).await.text().await.unwrap()
+}
+
+async fn send(server: &TestServer, email: &EmailReceived) -> reqwest::Response {
+    reqwest::Client::new().post(&server.url).json(email).send().await.unwrap()
+}
+
+#[tokio::test]
+async fn fixture_proof_is_rejected_and_nothing_is_sent() {
+    let verifier = Arc::new(Groth16Verifier {
+        verification_key_path: fixture_path("../verify_proof_lib/verification_key.json").display().to_string(),
+    });
+    let server = start_server(MembershipPolicy::Drop, verifier).await;
+    let proof = std::fs::read_to_string(fixture_path("../verify_proof_lib/proof.json")).unwrap();
+
+    let response = send(&server, &submission(&["alice", "bob"], &proof)).await;
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = response.text().await.unwrap();
+    assert!(response.contains("could not verify proof"), "{response}");
+    assert!(server.mailer.letters.lock().unwrap().is_empty());
+    assert!(server.emails.list(&EmailQuery::default()).unwrap().emails.is_empty());
+    // keys of a failed submission are not remembered
+    assert!(database_lib::key_history(&server.database.get().unwrap(), "alice").unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn real_proof_over_the_server_signals_is_accepted() {
+    let verification_key_path = std::env::temp_dir().join(format!("server_setup-{}-verification_key.json", std::process::id()));
+    let verifier = Arc::new(Groth16Verifier { verification_key_path: verification_key_path.display().to_string() });
+    let server = start_server(MembershipPolicy::Drop, verifier).await;
+    let email = submission(&["alice", "bob"], "");
+    let key_set = server.key_server.fetch_group_key_set(vec!["alice".into(), "bob".into()]).await.unwrap();
+    let message = CanonicalMessage::new(email.to.clone(), &email.header, &email.message, email.timestamp);
+    let public_signals = convert_publicSignals(create_pb_signals_from_key_set(&key_set, &message).unwrap()).await;
+    let (proof, verifying_key) = groth16_fixture(&public_signals);
+    std::fs::write(&verification_key_path, verifying_key).unwrap();
+
+    let response = post(&server, &EmailReceived { group_signature: proof.clone(), ..email.clone() }).await;
+    assert!(response.starts_with("Email sent!"), "{response}");
+    // the same proof does not cover another message
+    let response = send(&server, &EmailReceived { message: "Thanks for nothing".into(), group_signature: proof, ..email }).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = response.text().await.unwrap();
+    assert!(response.contains("signature is incorrect"), "{response}");
+    std::fs::remove_file(&verification_key_path).unwrap();
+
+    assert_eq!(server.mailer.letters.lock().unwrap().len(), 1);
+    assert_eq!(server.emails.list(&EmailQuery::default()).unwrap().emails.len(), 1);
+}
+
+#[tokio::test]
+async fn accepted_submission_is_mailed_and_archived() {
+    let verifier = Arc::new(RecordingVerifier::default());
+    let server = start_server(MembershipPolicy::Drop, verifier.clone()).await;
+    let email = submission(&["carol", "bob", "alice"], "{}");
+
+    let response = post(&server, &email).await;
+
+    assert!(response.starts_with("Email sent!"), "{response}");
+    assert!(response.contains("carol"), "{response}");
+
+    let key_set = server.key_server.fetch_group_key_set(vec!["alice".into(), "bob".into()]).await.unwrap();
+    let message = CanonicalMessage::new(email.to.clone(), &email.header, &email.message, email.timestamp);
+    let expected = convert_publicSignals(create_pb_signals_from_key_set(&key_set, &message).unwrap()).await;
+    assert_eq!(*verifier.public_signals.lock().unwrap(), vec![serde_json::to_string(&expected).unwrap()]);
+
+    let letters = server.mailer.letters.lock().unwrap().clone();
+    assert_eq!(letters.len(), 1);
+    assert!(letters[0].contains("To: team@example.org"));
+    assert!(letters[0].contains("bob\r\nalice\r\n"), "{}", letters[0]);
+    assert!(!letters[0].contains("carol"));
+    assert!(letters[0].contains(&format!("Date: {}", Utc::now().format("%a, %d %b %Y"))), "{}", letters[0]);
+
+    let archived: Vec<Email> = reqwest::get(&server.url).await.unwrap().json().await.unwrap();
+    assert_eq!(archived.len(), 1);
+    assert_eq!(archived[0].senders, vec!["bob", "alice"]);
+    assert_eq!(archived[0].status, EmailStatus::Sent);
+    assert_eq!(archived[0].timestamp, email.timestamp);
+    let by_sender: Vec<Email> = reqwest::get(format!("{}senders/Alice", server.url)).await.unwrap().json().await.unwrap();
+    assert_eq!(by_sender, archived);
+    let by_sender: Vec<Email> = reqwest::get(format!("{}senders/carol", server.url)).await.unwrap().json().await.unwrap();
+    assert!(by_sender.is_empty());
+}
+
+#[tokio::test]
+async fn timestamps_far_from_now_are_refused() {
+    let server = start_server(MembershipPolicy::Drop, Arc::new(RecordingVerifier::default())).await;
+    let now = Utc::now().timestamp() as u64;
+
+    for timestamp in [now - 2 * 3600, now + 2 * 3600, u64::MAX] {
+        let response = send(&server, &EmailReceived { timestamp: Some(timestamp), ..submission(&["alice"], "{}") }).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(response.text().await.unwrap().contains("60 minutes"));
+    }
+    assert!(server.mailer.letters.lock().unwrap().is_empty());
+    // a message without a timestamp has nothing to check
+    let response = post(&server, &EmailReceived { timestamp: None, ..submission(&["alice"], "{}") }).await;
+    assert!(response.starts_with("Email sent!"), "{response}");
+}
+
+#[tokio::test]
+async fn archive_is_listed_a_page_at_a_time() {
+    check_archive_listing(start_server(MembershipPolicy::Drop, Arc::new(RecordingVerifier::default())).await).await;
+}
+
+#[tokio::test]
+async fn archive_is_listed_a_page_at_a_time_from_sqlite() {
+    check_archive_listing(start_server_on_sqlite(MembershipPolicy::Drop, Arc::new(RecordingVerifier::default())).await).await;
+}
+
+async fn check_archive_listing(server: TestServer) {
+    for header in ["Hackathon kudos", "Demo day", "More hackathon kudos"] {
+        let mut email = submission(&["alice", "bob"], "{}");
+        email.header = header.to_string();
+        assert!(post(&server, &email).await.starts_with("Email sent!"));
+    }
+
+    let response = reqwest::get(format!("{}?limit=2&order=newest", server.url)).await.unwrap();
+    let link = response.headers().get(LINK).unwrap().to_str().unwrap().to_string();
+    let page: Vec<Email> = response.json().await.unwrap();
+    assert_eq!(page.iter().map(|email| email.header.as_str()).collect::<Vec<_>>(), ["More hackathon kudos", "Demo day"]);
+    let next = link.strip_prefix("</").and_then(|link| link.strip_suffix(">; rel=\"next\"")).unwrap();
+    let response = reqwest::get(format!("{}{}", server.url, next)).await.unwrap();
+    assert!(response.headers().get(LINK).is_none());
+    let page: Vec<Email> = response.json().await.unwrap();
+    assert_eq!(page.iter().map(|email| email.header.as_str()).collect::<Vec<_>>(), ["Hackathon kudos"]);
+
+    let page: Vec<Email> = reqwest::get(format!("{}?header=HACKATHON&sender=bob", server.url)).await.unwrap().json().await.unwrap();
+    assert_eq!(page.len(), 2);
+    let response = reqwest::get(format!("{}?since=yesterday", server.url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let hits: Vec<SearchHit> = reqwest::get(format!("{}search?q=hackathon", server.url)).await.unwrap().json().await.unwrap();
+    assert_eq!(hits.len(), 2);
+    assert!(hits.iter().all(|hit| hit.header.to_lowercase().contains("<mark>hackathon</mark>")), "{hits:?}");
+    let response = reqwest::get(format!("{}search?q=", server.url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn reject_policy_refuses_senders_without_keys() {
+    let server = start_server(MembershipPolicy::Reject, Arc::new(RecordingVerifier::default())).await;
+
+    let response = send(&server, &submission(&["alice", "carol"], "{}")).await;
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let text = response.text().await.unwrap();
+    assert!(text.contains("carol"), "{text}");
+    assert!(text.starts_with("Sorry"), "{text}");
+    assert!(server.mailer.letters.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn unknown_user_is_reported() {
+    let server = start_server(MembershipPolicy::Drop, Arc::new(RecordingVerifier::default())).await;
+
+    let response = send(&server, &submission(&["alice", "mallory"], "{}")).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let text = response.text().await.unwrap();
+    assert!(text.contains("mallory"), "{text}");
+    assert!(server.mailer.letters.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn key_server_rate_limit_is_passed_on() {
+    let server = start_server(MembershipPolicy::Drop, Arc::new(RecordingVerifier::default())).await;
+
+    let response = send(&server, &submission(&["alice", "throttled"], "{}")).await;
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[RETRY_AFTER], "60");
+    assert!(response.text().await.unwrap().contains("retry after 60 s"));
+}
+
+#[tokio::test]
+async fn verifying_key_is_chosen_by_key_family() {
+    let rsa = Arc::new(RecordingVerifier::default());
+    let ed25519 = Arc::new(RecordingVerifier::default());
+    let verifiers: HashMap<KeyType, Arc<dyn ProofVerifier>> =
+        HashMap::from([(KeyType::Rsa, rsa.clone() as Arc<dyn ProofVerifier>), (KeyType::Ed25519, ed25519.clone() as Arc<dyn ProofVerifier>)]);
+    let server = start_server_with(MembershipPolicy::Drop, verifiers, None).await;
+    let mut email = submission(&["carol", "bob"], "{}");
+    email.key_type = KeyType::Ed25519;
+
+    let response = post(&server, &email).await;
+
+    assert!(response.starts_with("Email sent!"), "{response}");
+    assert!(response.contains("no usable Ed25519 key") && response.contains("bob"), "{response}");
+    assert!(rsa.public_signals.lock().unwrap().is_empty());
+    let signals: Vec<String> = serde_json::from_str(&ed25519.public_signals.lock().unwrap()[0]).unwrap();
+    assert_eq!(signals.len(), 5 + MAX_GROUP_SIZE * KeyType::Ed25519.limbs_per_key());
+
+    email.key_type = KeyType::EcdsaP256;
+    let response = send(&server, &email).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = response.text().await.unwrap();
+    assert!(response.contains("does not accept proofs for ECDSA P-256 keys"), "{response}");
+}
+
+#[tokio::test]
+async fn mail_server_failures_are_reported_as_bad_gateway() {
+    let server = start_server(MembershipPolicy::Drop, Arc::new(RecordingVerifier::default())).await;
+    server.mailer.refuse.store(true, Ordering::SeqCst);
+
+    let response = send(&server, &submission(&["alice", "bob"], "{}")).await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(response.text().await.unwrap(), "Failed to send email: connection refused");
+    let archived = server.emails.list(&EmailQuery::default()).unwrap().emails;
+    assert_eq!(archived[0].status, EmailStatus::Failed);
+}
+
+#[tokio::test]
+async fn malformed_recipients_are_refused_before_archiving() {
+    let server = start_server(MembershipPolicy::Drop, Arc::new(RecordingVerifier::default())).await;
+    let email = EmailReceived { to: Some("not an address".to_string()), ..submission(&["alice", "bob"], "{}") };
+
+    let response = send(&server, &email).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.text().await.unwrap(), "Sorry, 'not an address' is not a valid recipient address.");
+    assert!(server.emails.list(&EmailQuery::default()).unwrap().emails.is_empty());
+    assert!(server.mailer.letters.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn proof_made_before_a_key_rotation_is_accepted_within_the_grace_period() {
+    // alice replaced an older key half an hour ago; the proof was made against it
+    let senders: Vec<String> = vec!["alice".into(), "bob".into()];
+    let key_server = start_key_server().await;
+    let current = key_server.fetch_group_key_set(senders.clone()).await.unwrap();
+    let bob_key = current.keys().iter().find(|key| key.usernames.contains(&"bob".to_string())).unwrap().public_key.clone();
+    let old_alice_key = vec![0x0a; 256];
+    let mut builder = GroupKeySetBuilder::new();
+    builder.add_key("alice", &old_alice_key).add_key("bob", &bob_key);
+    let old_key_set = builder.build().unwrap();
+
+    let email = submission(&["alice", "bob"], "{}");
+    let message = CanonicalMessage::new(email.to.clone(), &email.header, &email.message, email.timestamp);
+    let old_signals = convert_publicSignals(create_pb_signals_from_key_set(&old_key_set, &message).unwrap()).await;
+    let verifier = Arc::new(ExpectingVerifier { public_signals: serde_json::to_string(&old_signals).unwrap() });
+    let server = start_server(MembershipPolicy::Drop, verifier).await;
+
+    let now = Utc::now().timestamp();
+    {
+        let conn = server.database.get().unwrap();
+        record_seen_keys(&conn, "alice", &[("rsa".into(), old_alice_key.clone())], now - 7200).unwrap();
+        record_seen_keys(&conn, "alice", &[("rsa".into(), old_alice_key)], now - 1800).unwrap();
+        record_seen_keys(&conn, "bob", &[("rsa".into(), bob_key)], now - 7200).unwrap();
+    }
+
+    let response = post(&server, &email).await;
+    assert!(response.starts_with("Email sent!"), "{response}");
+    assert!(response.contains("verified against the group's keys as of"), "{response}");
+    // shown like every other date, with its zone
+    assert!(response.contains(" UTC; members have rotated keys since"), "{response}");
+    assert_eq!(server.mailer.letters.lock().unwrap().len(), 1);
+    // the fetch itself was recorded too
+    let history = database_lib::key_history(&server.database.get().unwrap(), "alice").unwrap();
+    assert!(history.iter().any(|key| key.first_seen >= now), "{history:?}");
+
+    // outside the grace period the old keys no longer count
+    server.database.get().unwrap()
+        .execute("UPDATE key_history SET first_seen = first_seen - 86400, last_seen = last_seen - 86400 WHERE public_key = ?1", [vec![0x0a_u8; 256]])
+        .unwrap();
+    assert_eq!(post(&server, &email).await, "Sorry, signature is incorrect");
+}
+
+#[tokio::test]
+async fn organization_and_team_references_are_resolved() {
+    let verifier = Arc::new(RecordingVerifier::default());
+    let server = start_server(MembershipPolicy::Drop, verifier.clone()).await;
+
+    let email = submission(&["team:parc/core", "carol"], "{}");
+    let response = post(&server, &email).await;
+
+    assert!(response.starts_with("Email sent!"), "{response}");
+    let archived = server.emails.list(&EmailQuery::default()).unwrap().emails;
+    assert_eq!(archived[0].members, vec!["bob", "alice", "carol"]);
+    assert_eq!(archived[0].senders, vec!["bob", "alice"]);
+    let by_sender: Vec<Email> = reqwest::get(format!("{}senders/Alice", server.url)).await.unwrap().json().await.unwrap();
+    assert_eq!(by_sender, archived);
+    let by_sender: Vec<Email> = reqwest::get(format!("{}senders/carol", server.url)).await.unwrap().json().await.unwrap();
+    assert!(by_sender.is_empty());
+
+    let response = post(&server, &EmailReceived { senders: vec!["org:parc".to_string()], ..email }).await;
+    assert!(response.starts_with("Email sent!"), "{response}");
+    // same group as above, so the same public signals
+    let signals = verifier.public_signals.lock().unwrap().clone();
+    assert_eq!(signals[0], signals[1]);
+
+    let response = send(&server, &submission(&["org:nobody"], "{}")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(response.text().await.unwrap().contains("org:nobody"));
+}
+
+#[tokio::test]
+async fn named_groups_are_managed_by_admins_and_pin_keys() {
+    let verifier = Arc::new(RecordingVerifier::default());
+    let server = start_server(MembershipPolicy::Drop, verifier.clone()).await;
+    let client = reqwest::Client::new();
+    let admin_url = format!("{}admin/groups", server.url);
+    let new_group = serde_json::json!({"name": "kudos-team", "members": ["team:parc/core", "carol"], "recipient": "kudos@example.org"});
+
+    let response = client.post(&admin_url).json(&new_group).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client.post(&admin_url).bearer_auth(ADMIN_TOKEN).json(&new_group).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let group: Group = response.json().await.unwrap();
+    assert_eq!(group.members, vec!["bob", "alice", "carol"]);
+    assert_eq!(group.max_keys, MAX_GROUP_SIZE);
+    let response = client.post(&admin_url).bearer_auth(ADMIN_TOKEN).json(&new_group).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // submissions name the group; recipient and keys come from it
+    let mut email = submission(&[], "{}");
+    email.to = None;
+    email.group = Some("kudos-team".to_string());
+    let response = post(&server, &email).await;
+    assert!(response.starts_with("Email sent!"), "{response}");
+    assert!(server.mailer.letters.lock().unwrap()[0].contains("To: kudos@example.org"));
+    let key_set = server.key_server.fetch_group_key_set(vec!["alice".into(), "bob".into()]).await.unwrap();
+    let message = CanonicalMessage::new(Some("kudos@example.org".to_string()), &email.header, &email.message, email.timestamp);
+    let expected = convert_publicSignals(create_pb_signals_from_key_set(&key_set, &message).unwrap()).await;
+    assert_eq!(verifier.public_signals.lock().unwrap()[0], serde_json::to_string(&expected).unwrap());
+
+    let response = client.put(format!("{}/kudos-team", admin_url)).bearer_auth(ADMIN_TOKEN)
+        .json(&serde_json::json!({"members": ["alice"], "max_keys": 10})).send().await.unwrap();
+    let group: Group = response.json().await.unwrap();
+    assert_eq!((group.members, group.max_keys), (vec!["alice".to_string()], 10));
+    let groups: Vec<Group> = client.get(&admin_url).bearer_auth(ADMIN_TOKEN).send().await.unwrap().json().await.unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].recipient.as_deref(), Some("kudos@example.org"));
+    let response = client.put(format!("{}/kudos-team", admin_url)).bearer_auth(ADMIN_TOKEN)
+        .json(&serde_json::json!({"recipient": null})).send().await.unwrap();
+    let group: Group = response.json().await.unwrap();
+    assert_eq!((group.recipient, group.max_keys), (None, 10));
+
+    let response = client.put(format!("{}/nobody", admin_url)).bearer_auth(ADMIN_TOKEN).json(&serde_json::json!({})).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    email.senders = vec!["alice".to_string()];
+    assert_eq!(send(&server, &email).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    email.senders.clear();
+    email.group = Some("unknown".to_string());
+    assert_eq!(send(&server, &email).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn groups_too_small_to_hide_the_sender_are_refused() {
+    let server = start_server(MembershipPolicy::Drop, Arc::new(RecordingVerifier::default())).await;
+    let client = reqwest::Client::new();
+    let admin_url = format!("{}admin/groups", server.url);
+    // carol publishes no keys, so only two members can have signed
+    let new_group = serde_json::json!({"name": "pair", "members": ["alice", "bob", "carol"], "min_members": 3});
+    let response = client.post(&admin_url).bearer_auth(ADMIN_TOKEN).json(&new_group).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let mut email = submission(&[], "{}");
+    email.group = Some("pair".to_string());
+    let response = send(&server, &email).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let text = response.text().await.unwrap();
+    assert!(text.contains("2 members with keys (at least 3 required)"), "{text}");
+    assert!(server.mailer.letters.lock().unwrap().is_empty());
+
+    let response = client.put(format!("{}/pair", admin_url)).bearer_auth(ADMIN_TOKEN)
+        .json(&serde_json::json!({"min_members": null})).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = post(&server, &email).await;
+    assert!(response.starts_with("Email sent!"), "{response}");
+}
+
+async fn request_challenge(server: &TestServer, username: &str, action: &str) -> consent::Challenge {
+    let request = serde_json::json!({"username": username, "action": action});
+    reqwest::Client::new().post(format!("{}consent/challenge", server.url)).json(&request).send().await.unwrap().json().await.unwrap()
+}
+
+/// The answer to `challenge`, signed with `fixtures/dave.pem`.
+fn signed_answer(challenge: &consent::Challenge) -> serde_json::Value {
+    let private_key = load_private_key_pem(&std::fs::read_to_string(fixture_path("fixtures/dave.pem")).unwrap()).unwrap();
+    let signature = sign_ssh_signature(&private_key, &challenge.namespace, challenge.challenge.as_bytes()).unwrap();
+    serde_json::json!({"username": challenge.username, "action": challenge.action, "nonce": challenge.nonce, "signature": signature})
+}
+
+async fn post_answer(server: &TestServer, answer: &serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new().post(format!("{}consent", server.url)).json(answer).send().await.unwrap()
+}
+
+/// Runs the challenge flow for `username`, signing with `fixtures/dave.pem`.
+async fn answer_challenge(server: &TestServer, username: &str, action: &str) -> reqwest::Response {
+    let challenge = request_challenge(server, username, action).await;
+    post_answer(server, &signed_answer(&challenge)).await
+}
+
+#[tokio::test]
+async fn only_members_who_opted_in_are_named() {
+    let verifiers: HashMap<KeyType, Arc<dyn ProofVerifier>> = HashMap::from([(KeyType::Rsa, Arc::new(RecordingVerifier::default()) as Arc<dyn ProofVerifier>)]);
+    let server = start_server_with(MembershipPolicy::Drop, verifiers, Some(MembershipPolicy::Drop)).await;
+
+    // alice cannot opt in with dave's key
+    assert_eq!(answer_challenge(&server, "alice", "opt-in").await.status(), StatusCode::FORBIDDEN);
+    let response = answer_challenge(&server, "dave", "opt-in").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.text().await.unwrap().contains("dave has opted in"));
+
+    let response = post(&server, &submission(&["alice", "dave"], "{}")).await;
+    assert!(response.starts_with("Email sent!"), "{response}");
+    assert!(response.contains("not opted in to being named: alice"), "{response}");
+    let letters = server.mailer.letters.lock().unwrap().clone();
+    assert!(letters[0].contains("dave") && !letters[0].contains("alice"), "{}", letters[0]);
+
+    assert_eq!(answer_challenge(&server, "dave", "opt-out").await.status(), StatusCode::OK);
+    // nobody who agreed to be named is left
+    let response = send(&server, &submission(&["alice", "dave"], "{}")).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // a challenge is answered once
+    let answer = signed_answer(&request_challenge(&server, "dave", "opt-in").await);
+    assert_eq!(post_answer(&server, &answer).await.status(), StatusCode::OK);
+    assert_eq!(post_answer(&server, &answer).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // GitHub usernames do not start with a dash
+    let request = serde_json::json!({"username": "-dave", "action": "opt-in"});
+    let response = reqwest::Client::new().post(format!("{}consent/challenge", server.url)).json(&request).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let answer = serde_json::json!({"username": "-dave", "action": "opt-in", "nonce": "00", "signature": ""});
+    let response = post_answer(&server, &answer).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.text().await.unwrap(), "Sorry, that is not a GitHub username.");
+}
+
+#[tokio::test]
+async fn others_cannot_cancel_or_burn_a_pending_challenge() {
+    let verifiers: HashMap<KeyType, Arc<dyn ProofVerifier>> = HashMap::from([(KeyType::Rsa, Arc::new(RecordingVerifier::default()) as Arc<dyn ProofVerifier>)]);
+    let server = start_server_with(MembershipPolicy::Drop, verifiers, Some(MembershipPolicy::Drop)).await;
+    let pending = request_challenge(&server, "dave", "opt-in").await;
+
+    // a third party asks for more challenges in dave's name and answers them,
+    // and dave's own, with garbage
+    for action in ["opt-in", "opt-out", "opt-in"] {
+        let spam = request_challenge(&server, "dave", action).await;
+        for nonce in [&spam.nonce, &pending.nonce] {
+            let garbage = serde_json::json!({"username": "dave", "action": action, "nonce": nonce, "signature": "-----BEGIN SSH SIGNATURE-----"});
+            assert_ne!(post_answer(&server, &garbage).await.status(), StatusCode::OK);
+        }
+    }
+
+    let response = post_answer(&server, &signed_answer(&pending)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.text().await.unwrap().contains("dave has opted in"));
+}
+
+#[tokio::test]
+async fn blocked_keys_are_left_out_and_blocked_users_refused() {
+    let verifier = Arc::new(RecordingVerifier::default());
+    let server = start_server(MembershipPolicy::Drop, verifier.clone()).await;
+    let client = reqwest::Client::new();
+    let blocklist_url = format!("{}admin/blocklist", server.url);
+    let alice_keys = parse_public_keys(&std::fs::read_to_string(fixture_path("fixtures/keys/alice.keys")).unwrap());
+    for key in &alice_keys.keys {
+        let request = serde_json::json!({"kind": "key", "value": key.fingerprint(), "reason": "leaked in a pastebin"});
+        let response = client.post(&blocklist_url).bearer_auth(ADMIN_TOKEN).json(&request).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let email = submission(&["alice", "bob"], "{}");
+    let response = post(&server, &email).await;
+    assert!(response.starts_with("Email sent!"), "{response}");
+    assert!(response.contains(&alice_keys.keys[0].fingerprint()), "{response}");
+    let bob_only = server.key_server.fetch_group_key_set(vec!["bob".into()]).await.unwrap();
+    let message = CanonicalMessage::new(email.to.clone(), &email.header, &email.message, email.timestamp);
+    let expected = convert_publicSignals(create_pb_signals_from_key_set(&bob_only, &message).unwrap()).await;
+    assert_eq!(verifier.public_signals.lock().unwrap()[0], serde_json::to_string(&expected).unwrap());
+
+    let ban = serde_json::json!({"kind": "user", "value": "bob", "reason": "impersonation"});
+    client.post(&blocklist_url).bearer_auth(ADMIN_TOKEN).json(&ban).send().await.unwrap();
+    let response = send(&server, &email).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(response.text().await.unwrap().contains("blocked and cannot be part of a group: bob"));
+
+    let response = client.delete(&blocklist_url).bearer_auth(ADMIN_TOKEN).json(&ban).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let trail: Vec<BlockAuditEntry> = client.get(format!("{}/audit", blocklist_url)).bearer_auth(ADMIN_TOKEN).send().await.unwrap().json().await.unwrap();
+    assert_eq!(trail.len(), alice_keys.keys.len() + 2);
+    assert_eq!((trail.last().unwrap().action.as_str(), trail.last().unwrap().reason.as_str()), ("remove", "impersonation"));
+}
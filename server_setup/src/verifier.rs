@@ -0,0 +1,25 @@
+use std::{future::Future, pin::Pin};
+use verify_proof_lib :: {verify_proof};
+
+pub type VerifyFuture<'a> = Pin<Box<dyn Future<Output = Result<bool, String>> + Send + 'a>>;
+
+/// Checks a group signature against the public signals derived by the server.
+pub trait ProofVerifier: Send + Sync {
+    /// `public_signals` is the JSON array of decimal strings.
+    fn verify<'a>(&'a self, proof: &'a str, public_signals: &'a str) -> VerifyFuture<'a>;
+}
+
+/// Groth16 verification against a snarkjs verification key on disk.
+pub struct Groth16Verifier{
+    pub verification_key_path: String,
+}
+
+impl ProofVerifier for Groth16Verifier{
+    fn verify<'a>(&'a self, proof: &'a str, public_signals: &'a str) -> VerifyFuture<'a>{
+        Box::pin(async move {
+            verify_proof(&proof.to_string(), &public_signals.to_string(), &self.verification_key_path)
+                .await
+                .map_err(|err| err.to_string())
+        })
+    }
+}